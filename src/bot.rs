@@ -0,0 +1,346 @@
+use crate::datastructures::{ClientType, Notification};
+use crate::socketlib::SocketConn;
+use anyhow::anyhow;
+use log::{info, warn};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use tokio::sync::watch;
+
+// Returned by setclientchannelgroup if client is already in that group
+const DUPLICATE_ENTRY: i32 = 2561;
+
+#[derive(Clone, Debug)]
+pub struct HerdRule {
+    watch: i64,
+    destination: i64,
+    exempt_groups: Vec<i64>,
+}
+
+impl HerdRule {
+    pub fn new(watch: i64, destination: i64, exempt_groups: Vec<i64>) -> Self {
+        Self {
+            watch,
+            destination,
+            exempt_groups,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PromoteRule {
+    uid: String,
+    cid: i64,
+    cgid: i64,
+}
+
+struct Bot<'a> {
+    conn: &'a mut SocketConn,
+    herd: Option<&'a HerdRule>,
+    promotions: &'a [PromoteRule],
+    me: i64,
+    database_ids: HashMap<String, i64>,
+}
+
+impl<'a> Bot<'a> {
+    async fn is_exempt(&mut self, rule: &HerdRule, client_id: i64) -> anyhow::Result<bool> {
+        if client_id == self.me {
+            return Ok(true);
+        }
+        let variables = self
+            .conn
+            .client_variables(client_id, &["client_type", "client_servergroups"])
+            .await?;
+        let client_type = match variables.get("client_type").map(|s| s.parse::<i64>()) {
+            Some(Ok(client_type)) => ClientType::from(client_type),
+            Some(Err(e)) => {
+                warn!(
+                    "Parse client {} type failed, assume regular: {}",
+                    client_id, e
+                );
+                ClientType::Regular
+            }
+            None => ClientType::Regular,
+        };
+        if client_type != ClientType::Regular {
+            return Ok(true);
+        }
+        Ok(variables
+            .get("client_servergroups")
+            .map(|groups| {
+                groups
+                    .split(',')
+                    .filter(|group| !group.is_empty())
+                    .filter_map(|group| match group.parse::<i64>() {
+                        Ok(group) => Some(group),
+                        Err(e) => {
+                            warn!("Parse client {} group {:?} failed: {}", client_id, group, e);
+                            None
+                        }
+                    })
+                    .any(|group| rule.exempt_groups.contains(&group))
+            })
+            .unwrap_or(false))
+    }
+
+    // Failure on single client should not stop the bot
+    async fn herd(&mut self, rule: &HerdRule, client_id: i64) {
+        match self.is_exempt(rule, client_id).await {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(e) => {
+                warn!("Query client {} groups failed: {}", client_id, e);
+                return;
+            }
+        }
+        match self
+            .conn
+            .move_client(client_id, rule.destination, None, false)
+            .await
+        {
+            Ok(_) => info!(
+                "Moved client {} from channel {} to {}",
+                client_id, rule.watch, rule.destination
+            ),
+            Err(e) => warn!("Move client {} failed: {}", client_id, e),
+        }
+    }
+
+    async fn database_id(&mut self, uid: &str) -> anyhow::Result<i64> {
+        if let Some(database_id) = self.database_ids.get(uid) {
+            return Ok(*database_id);
+        }
+        let database_id = self.conn.get_dbid_from_uid(uid).await?;
+        self.database_ids.insert(uid.to_string(), database_id);
+        Ok(database_id)
+    }
+
+    async fn promote(&mut self, client_id: i64, channel_id: i64, uid: Option<String>) {
+        if !self.promotions.iter().any(|rule| rule.cid == channel_id) {
+            return;
+        }
+        // Move event does not carry uid
+        let uid = match uid {
+            Some(uid) => uid,
+            None => match self
+                .conn
+                .client_variables(client_id, &["client_unique_identifier"])
+                .await
+            {
+                Ok(mut variables) => variables
+                    .remove("client_unique_identifier")
+                    .unwrap_or_default(),
+                Err(e) => {
+                    warn!("Query client {} uid failed: {}", client_id, e);
+                    return;
+                }
+            },
+        };
+        let promotions = self.promotions;
+        for rule in promotions
+            .iter()
+            .filter(|rule| rule.cid == channel_id && rule.uid == uid)
+        {
+            let database_id = match self.database_id(&rule.uid).await {
+                Ok(database_id) => database_id,
+                Err(e) => {
+                    warn!("Query database id of {} failed: {}", rule.uid, e);
+                    continue;
+                }
+            };
+            match self
+                .conn
+                .set_client_channel_group(rule.cgid, rule.cid, database_id)
+                .await
+            {
+                Ok(_) => info!(
+                    "Assigned channel group {} to {} in channel {}",
+                    rule.cgid, rule.uid, rule.cid
+                ),
+                Err(e) if e.code() == DUPLICATE_ENTRY => {}
+                Err(e) => warn!("Assign channel group to {} failed: {}", rule.uid, e),
+            }
+        }
+    }
+
+    async fn on_enter(&mut self, client_id: i64, channel_id: i64, uid: Option<String>) {
+        if let Some(rule) = self.herd {
+            if rule.watch == channel_id {
+                return self.herd(rule, client_id).await;
+            }
+        }
+        self.promote(client_id, channel_id, uid).await
+    }
+}
+
+// React to clients entering channels, by moving them away (herd rule) or by
+// assigning channel groups (promote rules)
+pub async fn event_staff(
+    mut conn: SocketConn,
+    mut exit: watch::Receiver<bool>,
+    herd: Option<HerdRule>,
+    promotions: Vec<PromoteRule>,
+) -> anyhow::Result<()> {
+    let mut events = conn
+        .take_events()
+        .ok_or_else(|| anyhow!("Event stream is already taken"))?;
+    // Bot listens until connection is dropped
+    conn.register_events()
+        .await
+        .map_err(|e| anyhow!("Register events error: {}", e))?
+        .keep();
+    let me = conn.who_am_i().await?.client_id();
+    // Clients already waiting in watched channel before events were registered
+    let waiting = match herd.as_ref() {
+        Some(rule) => conn.clients_in_channel(rule.watch).await?,
+        None => vec![],
+    };
+
+    let mut bot = Bot {
+        conn: &mut conn,
+        herd: herd.as_ref(),
+        promotions: &promotions,
+        me,
+        database_ids: HashMap::new(),
+    };
+    if let Some(rule) = herd.as_ref() {
+        for client in &waiting {
+            bot.herd(rule, client.client_id()).await;
+        }
+        info!(
+            "Watching channel {}, move clients to {}",
+            rule.watch, rule.destination
+        );
+    }
+
+    loop {
+        if *exit.borrow() {
+            info!("Exit!");
+            return Ok(());
+        }
+        tokio::select! {
+            ret = exit.changed() => {
+                if ret.is_err() {
+                    return Ok(());
+                }
+            }
+            event = events.recv() => match event {
+                Some(Ok(Notification::ClientEnterView(view))) => {
+                    bot.on_enter(
+                        view.client_id(),
+                        view.channel_id(),
+                        Some(view.client_unique_identifier().to_string()),
+                    )
+                    .await
+                }
+                Some(Ok(Notification::ClientMoved(moved))) => {
+                    bot.on_enter(moved.client_id(), moved.target_channel_id(), None)
+                        .await
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => warn!("Got error while parse event: {}", e),
+                None => return Err(anyhow!("Event stream closed")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bot::{Bot, HerdRule, PromoteRule};
+    use crate::socketlib::SocketConn;
+    use crate::testutil;
+    use std::collections::HashMap;
+
+    fn bot<'a>(
+        conn: &'a mut SocketConn,
+        herd: Option<&'a HerdRule>,
+        promotions: &'a [PromoteRule],
+    ) -> Bot<'a> {
+        Bot {
+            conn,
+            herd,
+            promotions,
+            me: 2,
+            database_ids: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_herd() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                (
+                    "clientvariable clid=5 client_type client_servergroups",
+                    "clid=5 client_type=0 client_servergroups=8,12\n\rerror id=0 msg=ok\n\r",
+                ),
+                (
+                    "clientvariable clid=6 client_type client_servergroups",
+                    "clid=6 client_type=0 client_servergroups=8\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("clientmove cid=9 clid=6", testutil::OK),
+                (
+                    "clientvariable clid=7 client_type client_servergroups",
+                    "clid=7 client_type=1 client_servergroups=8\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("whoami", testutil::WHOAMI),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let rule = HerdRule::new(3, 9, vec![12]);
+        let mut bot = bot(&mut conn, Some(&rule), &[]);
+        // Ourselves, nothing is sent
+        assert!(bot.is_exempt(&rule, 2).await.unwrap());
+        // Exempt group
+        bot.on_enter(5, 3, None).await;
+        // Regular client without exempt group is moved
+        bot.on_enter(6, 3, None).await;
+        // Query client
+        bot.on_enter(7, 3, None).await;
+        // Other channel, no promote rule either
+        bot.on_enter(6, 4, None).await;
+        // Script would fail on any extra move
+        assert_eq!(conn.who_am_i().await.unwrap().client_id(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_promote() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                (
+                    "clientgetdbidfromuid cluid=abc=",
+                    "cluid=abc= cldbid=7\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("setclientchannelgroup cgid=10 cid=4 cldbid=7", testutil::OK),
+                // Moved in, uid is looked up, database id is cached
+                (
+                    "clientvariable clid=5 client_unique_identifier",
+                    "clid=5 client_unique_identifier=abc=\n\rerror id=0 msg=ok\n\r",
+                ),
+                (
+                    "setclientchannelgroup cgid=10 cid=4 cldbid=7",
+                    "error id=2561 msg=duplicate\\sentry\n\r",
+                ),
+                ("whoami", testutil::WHOAMI),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let promotions = [PromoteRule {
+            uid: "abc=".to_string(),
+            cid: 4,
+            cgid: 10,
+        }];
+        let mut bot = bot(&mut conn, None, &promotions);
+        bot.on_enter(5, 4, Some("abc=".to_string())).await;
+        bot.on_enter(5, 4, None).await;
+        // Neither uid nor channel has a rule
+        bot.on_enter(6, 4, Some("other=".to_string())).await;
+        bot.on_enter(5, 3, Some("abc=".to_string())).await;
+        assert_eq!(bot.database_ids.get("abc="), Some(&7));
+        // Duplicate entry did not break the connection
+        assert_eq!(conn.who_am_i().await.unwrap().client_id(), 2);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::datastructures::{Notification, QueryResult, TextMessage};
+use tokio::sync::mpsc;
+
+#[derive(Clone, Debug)]
+pub struct ChatCommand {
+    message: TextMessage,
+    command: String,
+    args: Vec<String>,
+}
+
+impl ChatCommand {
+    // None if message does not start with prefix or has no command after it
+    pub fn parse(message: TextMessage, prefix: &str) -> Option<Self> {
+        let mut words = message.message().strip_prefix(prefix)?.split_whitespace();
+        let command = words.next()?.to_string();
+        let args = words.map(str::to_string).collect();
+        Some(Self {
+            message,
+            command,
+            args,
+        })
+    }
+
+    pub fn invoker_id(&self) -> i64 {
+        self.message.invoker_id()
+    }
+    pub fn invoker_name(&self) -> &str {
+        self.message.invoker_name()
+    }
+    pub fn invoker_uid(&self) -> &str {
+        self.message.invoker_uid()
+    }
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+    pub fn message(&self) -> &TextMessage {
+        &self.message
+    }
+}
+
+// Turn the event stream into chat commands, every other event is discarded
+pub struct ChatCommands {
+    events: mpsc::Receiver<QueryResult<Notification>>,
+    prefix: String,
+}
+
+impl ChatCommands {
+    pub fn new(events: mpsc::Receiver<QueryResult<Notification>>, prefix: &str) -> Self {
+        Self {
+            events,
+            prefix: prefix.to_string(),
+        }
+    }
+
+    // None once event stream is closed
+    pub async fn next(&mut self) -> Option<ChatCommand> {
+        loop {
+            if let Ok(Notification::TextMessage(message)) = self.events.recv().await? {
+                if let Some(command) = ChatCommand::parse(message, &self.prefix) {
+                    return Some(command);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chat::ChatCommands;
+    use crate::datastructures::Notification;
+
+    #[tokio::test]
+    async fn test_chat_commands() {
+        let (sender, receiver) = tokio::sync::mpsc::channel(8);
+        for line in [
+            "notifytextmessage targetmode=2 msg=hello invokerid=3 invokername=Guest",
+            "notifyclientleftview schandlerid=1 cfid=1 ctid=0 reasonid=8 clid=5",
+            "notifytextmessage targetmode=1 msg=! invokerid=3 invokername=Guest",
+            "notifytextmessage targetmode=1 msg=!move\\s5\\s\\s7 invokerid=2 invokername=Admin invokeruid=xyz=",
+        ] {
+            sender.send(Notification::from_line(line)).await.unwrap();
+        }
+        drop(sender);
+        let mut commands = ChatCommands::new(receiver, "!");
+        let command = commands.next().await.unwrap();
+        assert_eq!(command.invoker_id(), 2);
+        assert_eq!(command.invoker_uid(), "xyz=");
+        assert_eq!(command.command(), "move");
+        assert_eq!(command.args(), ["5", "7"]);
+        assert!(commands.next().await.is_none());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::bot::{HerdRule, PromoteRule};
+use anyhow::anyhow;
+use serde_derive::Deserialize;
+use std::fmt::{Debug, Display, Formatter};
+use std::path::Path;
+
+const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_PORT: u16 = 25639;
+const DEFAULT_MIN_INTERVAL: u64 = 50;
+const DEFAULT_MAX_INTERVAL: u64 = 70;
+
+// Api key which is never printed, the raw value is only available through `expose`
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct SecretKey(String);
+
+impl SecretKey {
+    pub fn new(key: String) -> Self {
+        Self(key)
+    }
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for SecretKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("***")
+    }
+}
+
+impl Display for SecretKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("***")
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    api_key: Option<SecretKey>,
+    host: String,
+    port: u16,
+    min_interval: u64,
+    max_interval: u64,
+    keepalive: Option<u64>,
+    // Seconds to wait for auth reply, busy client may take a while
+    login_timeout: Option<u64>,
+    dry_run: bool,
+    once: bool,
+    // Write original description back on clean shutdown
+    restore: bool,
+    metrics_listen: Option<String>,
+    watch_channel: Option<i64>,
+    move_to: Option<i64>,
+    exempt_groups: Vec<i64>,
+    // Config file only, `[[promote]]` tables with uid, cid and cgid
+    promote: Vec<PromoteRule>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            host: DEFAULT_HOST.to_string(),
+            port: DEFAULT_PORT,
+            min_interval: DEFAULT_MIN_INTERVAL,
+            max_interval: DEFAULT_MAX_INTERVAL,
+            keepalive: None,
+            login_timeout: None,
+            dry_run: false,
+            once: false,
+            restore: true,
+            metrics_listen: None,
+            watch_channel: None,
+            move_to: None,
+            exempt_groups: vec![],
+            promote: vec![],
+        }
+    }
+}
+
+impl Config {
+    // `.json` files are parsed as JSON, everything else as TOML
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Read config file {} error: {:?}", path.display(), e))?;
+        if path.extension().map(|ext| ext == "json").unwrap_or(false) {
+            serde_json::from_str(&content)
+                .map_err(|e| anyhow!("Parse config file {} error: {}", path.display(), e))
+        } else {
+            toml::from_str(&content)
+                .map_err(|e| anyhow!("Parse config file {} error: {}", path.display(), e))
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.min_interval >= self.max_interval {
+            return Err(anyhow!(
+                "min_interval ({}) should be less than max_interval ({})",
+                self.min_interval,
+                self.max_interval
+            ));
+        }
+        if self.watch_channel.is_some() != self.move_to.is_some() {
+            return Err(anyhow!("watch_channel and move_to should be set together"));
+        }
+        if self.watch_channel.is_some() && self.watch_channel == self.move_to {
+            return Err(anyhow!("move_to should be different from watch_channel"));
+        }
+        if self.once && (self.watch_channel.is_some() || !self.promote.is_empty()) {
+            return Err(anyhow!(
+                "once can not be used with watch_channel or promote"
+            ));
+        }
+        if self.keepalive == Some(0) {
+            return Err(anyhow!("keepalive should be greater than 0"));
+        }
+        if self.login_timeout == Some(0) {
+            return Err(anyhow!("login_timeout should be greater than 0"));
+        }
+        Ok(())
+    }
+
+    pub fn api_key(&self) -> Option<&SecretKey> {
+        self.api_key.as_ref()
+    }
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    pub fn min_interval(&self) -> u64 {
+        self.min_interval
+    }
+    pub fn max_interval(&self) -> u64 {
+        self.max_interval
+    }
+    pub fn keepalive(&self) -> Option<u64> {
+        self.keepalive
+    }
+    pub fn login_timeout(&self) -> Option<u64> {
+        self.login_timeout
+    }
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+    pub fn once(&self) -> bool {
+        self.once
+    }
+    pub fn restore(&self) -> bool {
+        self.restore
+    }
+    pub fn metrics_listen(&self) -> Option<&str> {
+        self.metrics_listen.as_deref()
+    }
+    pub fn promote_rules(&self) -> &[PromoteRule] {
+        &self.promote
+    }
+    pub fn herd_rule(&self) -> Option<HerdRule> {
+        Some(HerdRule::new(
+            self.watch_channel?,
+            self.move_to?,
+            self.exempt_groups.clone(),
+        ))
+    }
+
+    pub fn set_api_key(&mut self, api_key: String) {
+        self.api_key.replace(SecretKey::new(api_key));
+    }
+    pub fn set_host(&mut self, host: String) {
+        self.host = host;
+    }
+    pub fn set_port(&mut self, port: u16) {
+        self.port = port;
+    }
+    pub fn set_min_interval(&mut self, min_interval: u64) {
+        self.min_interval = min_interval;
+    }
+    pub fn set_max_interval(&mut self, max_interval: u64) {
+        self.max_interval = max_interval;
+    }
+    pub fn set_keepalive(&mut self, keepalive: u64) {
+        self.keepalive.replace(keepalive);
+    }
+    pub fn set_login_timeout(&mut self, login_timeout: u64) {
+        self.login_timeout.replace(login_timeout);
+    }
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+    pub fn set_once(&mut self, once: bool) {
+        self.once = once;
+    }
+    pub fn set_restore(&mut self, restore: bool) {
+        self.restore = restore;
+    }
+    pub fn set_metrics_listen(&mut self, metrics_listen: String) {
+        self.metrics_listen.replace(metrics_listen);
+    }
+    pub fn set_watch_channel(&mut self, watch_channel: i64) {
+        self.watch_channel.replace(watch_channel);
+    }
+    pub fn set_move_to(&mut self, move_to: i64) {
+        self.move_to.replace(move_to);
+    }
+    pub fn set_exempt_groups(&mut self, exempt_groups: Vec<i64>) {
+        self.exempt_groups = exempt_groups;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::{Config, SecretKey};
+
+    #[test]
+    fn test() {
+        let config: Config = toml::from_str("api_key = \"key\"\nport = 25640").unwrap();
+        assert_eq!(config.api_key().map(SecretKey::expose), Some("key"));
+        assert!(!format!("{:?}", config).contains("key\""));
+        assert_eq!(config.host(), "localhost");
+        assert_eq!(config.port(), 25640);
+        assert_eq!(config.keepalive(), None);
+        assert_eq!(config.login_timeout(), None);
+        assert!(config.restore());
+        config.validate().unwrap();
+
+        let config: Config =
+            serde_json::from_str(r#"{"min_interval": 30, "max_interval": 10}"#).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str("login_timeout = 60").unwrap();
+        assert_eq!(config.login_timeout(), Some(60));
+        config.validate().unwrap();
+        let config: Config = toml::from_str("login_timeout = 0").unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str("watch_channel = 3").unwrap();
+        assert!(config.validate().is_err());
+        let config: Config =
+            toml::from_str("watch_channel = 3\nmove_to = 1\nexempt_groups = [6, 9]").unwrap();
+        config.validate().unwrap();
+        assert!(config.herd_rule().is_some());
+        let config: Config = toml::from_str("watch_channel = 3\nmove_to = 1\nonce = true").unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str(
+            "[[promote]]\nuid = \"abc=\"\ncid = 2\ncgid = 5\n\n[[promote]]\nuid = \"def=\"\ncid = 2\ncgid = 6",
+        )
+        .unwrap();
+        assert_eq!(config.promote_rules().len(), 2);
+    }
+}
@@ -1,11 +1,89 @@
 pub trait FromQueryString: for<'de> Deserialize<'de> {
-    fn from_query(data: &str) -> anyhow::Result<Self>
+    fn from_query(data: &str) -> QueryResult<Self>
     where
         Self: Sized,
     {
-        serde_teamspeak_querystring::from_str(data)
-            .map_err(|e| anyhow::anyhow!("Got parser error: {:?}, original => {:?}", e, data))
+        serde_teamspeak_querystring::from_str(&expand_escapes(data)).map_err(|e| {
+            QueryError::parse_error(match error_field(&e) {
+                Some(field) => format!(
+                    "Got parser error on field {:?}: {}, original => {:?}",
+                    field, e.message, data
+                ),
+                None => format!("Got parser error: {:?}, original => {:?}", e, data),
+            })
+        })
     }
+
+    fn from_query_multi(line: &str) -> QueryResult<Vec<Self>>
+    where
+        Self: Sized,
+    {
+        line.split('|').map(Self::from_query).collect()
+    }
+}
+
+impl FromQueryString for HashMap<String, String> {}
+
+// Missing field error raised by serde does not carry key, but mention it in message
+fn error_field(e: &serde_teamspeak_querystring::Error) -> Option<String> {
+    if !e.key.is_empty() {
+        return Some(e.key.clone());
+    }
+    e.message
+        .strip_prefix("missing field `")
+        .and_then(|s| s.split_once('`'))
+        .map(|(field, _)| field.to_string())
+}
+
+// Parser only knows `\\`, `\s` and `\/` and drops any other escape, so `\p` and
+// control characters are expanded before parsing
+fn expand_escapes(s: &str) -> Cow<'_, str> {
+    if !s.contains('\\') {
+        return Cow::Borrowed(s);
+    }
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('p') => ret.push('|'),
+            Some('n') => ret.push('\n'),
+            Some('r') => ret.push('\r'),
+            Some('t') => ret.push('\t'),
+            Some(c) => {
+                ret.push('\\');
+                ret.push(c);
+            }
+            None => ret.push('\\'),
+        }
+    }
+    Cow::Owned(ret)
+}
+
+// Reverse of server side escaping, for values not going through serde
+fn unescape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => ret.push(' '),
+            Some('p') => ret.push('|'),
+            Some('/') => ret.push('/'),
+            Some('n') => ret.push('\n'),
+            Some('r') => ret.push('\r'),
+            Some('t') => ret.push('\t'),
+            Some(c) => ret.push(c),
+            None => ret.push('\\'),
+        }
+    }
+    ret
 }
 
 fn from_str<'de, T, D>(deserializer: D) -> Result<T, D::Error>
@@ -18,11 +96,45 @@ where
     T::from_str(&s).map_err(serde::de::Error::custom)
 }
 
+// Flags come as 0 or 1
+fn from_int_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match String::deserialize(deserializer)?.as_str() {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        s => Err(serde::de::Error::custom(format!("invalid flag {:?}", s))),
+    }
+}
+
+// Human readable form of uptime like fields, e.g. "1d 2h 0m 5s", leading zero units
+// are omitted
+#[allow(dead_code)]
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let units = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+    let start = units
+        .iter()
+        .position(|(value, _)| *value != 0)
+        .unwrap_or(units.len() - 1);
+    units[start..]
+        .iter()
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 pub mod whoami {
     use super::{from_str, FromQueryString};
-    use serde_derive::Deserialize;
+    use serde_derive::{Deserialize, Serialize};
 
-    #[derive(Clone, Debug, Default, Deserialize)]
+    #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
     pub struct WhoAmI {
         #[serde(deserialize_with = "from_str", rename = "clid")]
         client_id: i64,
@@ -40,13 +152,33 @@ pub mod whoami {
     }
 
     impl FromQueryString for WhoAmI {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::whoami::WhoAmI;
+        use crate::datastructures::{FromQueryString, QueryError};
+
+        #[test]
+        fn test_error_field() {
+            let err = WhoAmI::from_query("clid=1").unwrap_err();
+            assert_eq!(err.code(), QueryError::PARSE_ERROR);
+            assert!(err.to_string().contains("field \"cid\""), "{}", err);
+        }
+
+        #[test]
+        fn test_minimal() {
+            let result = WhoAmI::from_query("clid=1 cid=2").unwrap();
+            assert_eq!(result.client_id(), 1);
+            assert_eq!(result.channel_id(), 2);
+        }
+    }
 }
 
 pub mod create_channel {
     use super::{from_str, FromQueryString};
-    use serde_derive::Deserialize;
+    use serde_derive::{Deserialize, Serialize};
 
-    #[derive(Clone, Debug, Default, Deserialize)]
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
     pub struct CreateChannel {
         #[serde(deserialize_with = "from_str")]
         cid: i64,
@@ -61,21 +193,41 @@ pub mod create_channel {
     impl FromQueryString for CreateChannel {}
 }
 
+pub mod ban_add {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct BanAdd {
+        #[serde(deserialize_with = "from_str")]
+        banid: i64,
+    }
+
+    impl BanAdd {
+        pub fn ban_id(&self) -> i64 {
+            self.banid
+        }
+    }
+
+    impl FromQueryString for BanAdd {}
+}
+
 pub mod channel {
     use super::{from_str, FromQueryString};
-    use serde_derive::Deserialize;
+    use serde_derive::{Deserialize, Serialize};
 
     #[allow(dead_code)]
-    #[derive(Clone, Debug, Default, Deserialize)]
+    #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
     pub struct Channel {
         #[serde(deserialize_with = "from_str")]
         cid: i64,
-        #[serde(deserialize_with = "from_str")]
+        #[serde(default, deserialize_with = "from_str")]
         pid: i64,
-        #[serde(deserialize_with = "from_str")]
+        #[serde(default, deserialize_with = "from_str")]
         channel_order: i64,
+        #[serde(default)]
         channel_name: String,
-        #[serde(deserialize_with = "from_str")]
+        #[serde(default, deserialize_with = "from_str")]
         total_clients: i64,
     }
 
@@ -99,248 +251,2188 @@ pub mod channel {
     }
 
     impl FromQueryString for Channel {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::channel::Channel;
+        use crate::datastructures::FromQueryString;
+
+        #[test]
+        fn test_error_field() {
+            let err =
+                Channel::from_query("pid=0 channel_order=0 channel_name=Default total_clients=1")
+                    .unwrap_err();
+            assert!(err.to_string().contains("field \"cid\""), "{}", err);
+        }
+
+        #[test]
+        fn test_minimal() {
+            let result = Channel::from_query("cid=2").unwrap();
+            assert_eq!(result.cid(), 2);
+            assert_eq!(result.channel_name(), "");
+        }
+    }
 }
 
-pub mod client {
-    use super::from_str;
-    use super::FromQueryString;
-    use serde_derive::Deserialize;
+pub mod channel_find {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
 
-    #[allow(dead_code)]
-    #[derive(Clone, Debug, Default, Deserialize)]
-    pub struct Client {
-        #[serde(deserialize_with = "from_str")]
-        clid: i64,
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ChannelFind {
         #[serde(deserialize_with = "from_str")]
         cid: i64,
-        #[serde(deserialize_with = "from_str")]
-        client_database_id: i64,
-        #[serde(deserialize_with = "from_str")]
-        client_type: i64,
-        //client_unique_identifier: String,
-        client_nickname: String,
+        #[serde(default)]
+        channel_name: String,
     }
 
-    #[allow(dead_code)]
-    impl Client {
-        pub fn client_id(&self) -> i64 {
-            self.clid
-        }
-        pub fn channel_id(&self) -> i64 {
+    impl ChannelFind {
+        pub fn cid(&self) -> i64 {
             self.cid
         }
-        pub fn client_database_id(&self) -> i64 {
-            self.client_database_id
-        }
-        pub fn client_type(&self) -> i64 {
-            self.client_type
-        }
-        pub fn client_unique_identifier(&self) -> String {
-            format!("{}", self.client_database_id)
-        }
-        pub fn client_nickname(&self) -> &str {
-            &self.client_nickname
+        pub fn channel_name(&self) -> &str {
+            &self.channel_name
         }
     }
 
-    impl FromQueryString for Client {}
+    impl FromQueryString for ChannelFind {}
 
     #[cfg(test)]
     mod test {
-        use crate::datastructures::client::Client;
+        use crate::datastructures::channel_find::ChannelFind;
         use crate::datastructures::FromQueryString;
 
-        const TEST_STRING: &str = "clid=8 cid=1 client_database_id=1 client_nickname=serveradmin client_type=1 client_unique_identifier=serveradmin";
-
         #[test]
-        fn test() {
-            let result = Client::from_query(TEST_STRING).unwrap();
-            assert_eq!(result.client_id(), 8);
-            assert_eq!(result.channel_id(), 1);
-            assert_eq!(result.client_database_id(), 1);
-            assert_eq!(result.client_nickname(), "serveradmin".to_string());
-            assert_eq!(result.client_type(), 1);
-            assert_eq!(result.client_unique_identifier(), "serveradmin".to_string());
+        fn test_minimal() {
+            let result = ChannelFind::from_query("cid=4").unwrap();
+            assert_eq!(result.cid(), 4);
+            assert_eq!(result.channel_name(), "");
         }
     }
 }
 
-pub mod query_status {
-    use crate::datastructures::{QueryError, QueryResult};
-    use anyhow::anyhow;
-    use serde_derive::Deserialize;
+pub mod channel_info {
+    use super::{from_int_bool, from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
 
-    #[allow(dead_code)]
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct QueryStatus {
-        id: i32,
-        msg: String,
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ChannelInfo {
+        #[serde(default, deserialize_with = "from_str")]
+        pid: i64,
+        channel_name: String,
+        #[serde(default)]
+        channel_topic: String,
+        #[serde(default)]
+        channel_description: String,
+        #[serde(default, deserialize_with = "from_int_bool")]
+        channel_flag_password: bool,
+        #[serde(default, deserialize_with = "from_str")]
+        channel_maxclients: i64,
     }
 
-    impl Default for QueryStatus {
-        fn default() -> Self {
-            Self {
-                id: 0,
-                msg: "ok".to_string(),
-            }
+    impl ChannelInfo {
+        pub fn pid(&self) -> i64 {
+            self.pid
         }
-    }
-
-    impl QueryStatus {
-        pub fn id(&self) -> i32 {
-            self.id
+        pub fn channel_name(&self) -> &str {
+            &self.channel_name
         }
-        pub fn msg(&self) -> &String {
-            &self.msg
+        pub fn channel_topic(&self) -> &str {
+            &self.channel_topic
         }
-
-        pub fn into_err(self) -> QueryError {
-            QueryError::from(self)
+        pub fn channel_description(&self) -> &str {
+            &self.channel_description
         }
-
-        pub fn into_result<T>(self, ret: T) -> QueryResult<T> {
-            if self.id == 0 {
-                return Ok(ret);
-            }
-            Err(self.into_err())
+        pub fn channel_flag_password(&self) -> bool {
+            self.channel_flag_password
+        }
+        pub fn channel_maxclients(&self) -> i64 {
+            self.channel_maxclients
         }
     }
 
-    impl TryFrom<&str> for QueryStatus {
-        type Error = QueryError;
+    impl FromQueryString for ChannelInfo {}
 
-        fn try_from(value: &str) -> Result<Self, Self::Error> {
-            let (_, line) = value
-                .split_once("error ")
-                .ok_or_else(|| anyhow!("Split error: {}", value))?;
-            serde_teamspeak_querystring::from_str(line)
-                .map_err(|e| QueryError::parse_error(format!("Parse {:?} error: {:?}", line, e)))
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::channel_info::ChannelInfo;
+        use crate::datastructures::FromQueryString;
+
+        #[test]
+        fn test() {
+            let result = ChannelInfo::from_query(
+                "pid=1 channel_name=Private channel_topic=Keep\\sout channel_flag_password=1 channel_maxclients=5",
+            )
+            .unwrap();
+            assert_eq!(result.pid(), 1);
+            assert_eq!(result.channel_topic(), "Keep out");
+            assert!(result.channel_flag_password());
+            assert_eq!(result.channel_maxclients(), 5);
+            assert!(
+                ChannelInfo::from_query("channel_name=Lobby channel_flag_password=yes").is_err()
+            );
+        }
+
+        #[test]
+        fn test_minimal() {
+            let result = ChannelInfo::from_query("channel_name=Lobby").unwrap();
+            assert_eq!(result.channel_name(), "Lobby");
+            assert_eq!(result.pid(), 0);
+            assert!(!result.channel_flag_password());
+            assert_eq!(result.channel_maxclients(), 0);
         }
     }
 }
 
-pub mod connect_info {
-    use super::FromQueryString;
-    use serde_derive::Deserialize;
+pub mod connection_info {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
 
-    #[derive(Clone, Debug, Deserialize)]
-    pub struct ConnectInfo {
-        ip: String,
-        port: u16,
+    // Some of these fields require extra permissions, the server omits them silently
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ConnectionInfo {
+        #[serde(default, deserialize_with = "from_str")]
+        connection_ping: u64,
+        #[serde(default, deserialize_with = "from_str")]
+        connection_packets_sent_total: u64,
+        #[serde(default, deserialize_with = "from_str")]
+        connection_bytes_received_total: u64,
+        #[serde(default)]
+        connection_client_ip: String,
     }
 
-    impl ConnectInfo {
-        pub fn ip(&self) -> &str {
-            &self.ip
+    impl ConnectionInfo {
+        pub fn ping(&self) -> u64 {
+            self.connection_ping
         }
-        pub fn port(&self) -> u16 {
-            self.port
+        pub fn packets_sent_total(&self) -> u64 {
+            self.connection_packets_sent_total
+        }
+        pub fn bytes_received_total(&self) -> u64 {
+            self.connection_bytes_received_total
+        }
+        pub fn client_ip(&self) -> &str {
+            &self.connection_client_ip
         }
     }
 
-    impl FromQueryString for ConnectInfo {}
-}
+    impl FromQueryString for ConnectionInfo {}
 
-mod status_result {
-    use crate::datastructures::QueryStatus;
-    use anyhow::Error;
-    use std::fmt::{Display, Formatter};
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::connection_info::ConnectionInfo;
+        use crate::datastructures::FromQueryString;
 
-    pub type QueryResult<T> = Result<T, QueryError>;
+        #[test]
+        fn test() {
+            let result = ConnectionInfo::from_query(
+                "cid=1 connection_ping=23 connection_packets_sent_total=1024 connection_bytes_received_total=65536 connection_client_ip=127.0.0.1",
+            )
+            .unwrap();
+            assert_eq!(result.ping(), 23);
+            assert_eq!(result.packets_sent_total(), 1024);
+            assert_eq!(result.bytes_received_total(), 65536);
+            assert_eq!(result.client_ip(), "127.0.0.1");
 
-    #[derive(Clone, Default, Debug)]
-    pub struct QueryError {
-        code: i32,
-        message: String,
+            let result = ConnectionInfo::from_query("cid=1 connection_ping=23").unwrap();
+            assert_eq!(result.bytes_received_total(), 0);
+            assert_eq!(result.client_ip(), "");
+        }
+
+        #[test]
+        fn test_minimal() {
+            let result = ConnectionInfo::from_query("cid=1").unwrap();
+            assert_eq!(result.ping(), 0);
+            assert_eq!(result.packets_sent_total(), 0);
+            assert_eq!(result.client_ip(), "");
+        }
+    }
+}
+
+pub mod api_key {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    #[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ApiKeyScope {
+        #[default]
+        Manage,
+        Write,
+        Read,
     }
 
-    impl QueryError {
-        pub fn static_empty_response() -> Self {
-            Self {
-                code: -1,
-                message: "Expect result but none found.".to_string(),
+    impl ApiKeyScope {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                Self::Manage => "manage",
+                Self::Write => "write",
+                Self::Read => "read",
             }
         }
-        pub fn database_id_error() -> Self {
-            Self {
-                code: -3,
-                message: "Can't get self database_id".to_string(),
+
+        fn rank(&self) -> u8 {
+            match self {
+                Self::Read => 0,
+                Self::Write => 1,
+                Self::Manage => 2,
             }
         }
-        pub fn status_not_found() -> Self {
-            Self {
-                code: -4,
-                message: "Status line not found".to_string(),
-            }
+
+        // Manage includes write, write includes read
+        pub fn allows(&self, required: ApiKeyScope) -> bool {
+            self.rank() >= required.rank()
         }
-        pub fn split_error(value: &str) -> Self {
-            Self {
-                code: -5,
-                message: format!("Split error {}", value),
-            }
+    }
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ApiKey {
+        #[serde(deserialize_with = "from_str")]
+        id: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        cldbid: i64,
+        #[serde(default)]
+        scope: ApiKeyScope,
+        // Seconds, or `unlimited`
+        #[serde(default)]
+        time_left: String,
+        #[serde(default, deserialize_with = "from_str")]
+        created_at: i64,
+        // Only returned by apikeyadd
+        #[serde(default)]
+        apikey: String,
+    }
+
+    impl ApiKey {
+        pub fn id(&self) -> i64 {
+            self.id
         }
-        pub fn parse_error(message: String) -> Self {
-            Self { code: -6, message }
+        pub fn client_database_id(&self) -> i64 {
+            self.cldbid
         }
-        pub fn result_not_found(payload: &str) -> Self {
-            Self {
-                code: -7,
-                message: format!("Result not found: {:?}", payload),
-            }
+        pub fn scope(&self) -> ApiKeyScope {
+            self.scope
         }
-        pub fn query_error(payload: &str) -> Self {
-            Self {
-                code: -8,
-                message: format!("Query client error: {:?}", payload),
-            }
+        pub fn time_left(&self) -> Option<Duration> {
+            self.time_left.parse().ok().map(Duration::from_secs)
         }
-        pub fn code(&self) -> i32 {
-            self.code
+        pub fn created_at(&self) -> i64 {
+            self.created_at
         }
-    }
-
-    impl Display for QueryError {
-        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{}({})", self.message, self.code)
+        pub fn key(&self) -> &str {
+            &self.apikey
         }
     }
 
-    impl std::error::Error for QueryError {}
+    impl FromQueryString for ApiKey {}
 
-    impl From<QueryStatus> for QueryError {
-        fn from(status: QueryStatus) -> Self {
-            Self {
-                code: status.id(),
-                message: status.msg().clone(),
-            }
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::api_key::{ApiKey, ApiKeyScope};
+        use crate::datastructures::FromQueryString;
+        use std::time::Duration;
+
+        #[test]
+        fn test() {
+            let result = ApiKey::from_query_multi(
+                "id=1 sid=0 cldbid=1 scope=manage time_left=unlimited created_at=1600000000 expires_at=1600000000|id=2 sid=0 cldbid=1 scope=read time_left=86400 created_at=1600000000 expires_at=1600086400",
+            )
+            .unwrap();
+            assert_eq!(result.len(), 2);
+            assert_eq!(result[0].scope(), ApiKeyScope::Manage);
+            assert_eq!(result[0].time_left(), None);
+            assert_eq!(result[1].id(), 2);
+            assert_eq!(result[1].scope(), ApiKeyScope::Read);
+            assert_eq!(result[1].time_left(), Some(Duration::from_secs(86400)));
+            assert_eq!(result[1].created_at(), 1600000000);
+            assert_eq!(result[1].client_database_id(), 1);
         }
-    }
 
-    impl From<Error> for QueryError {
-        fn from(s: Error) -> Self {
-            Self {
-                code: -114514,
-                message: s.to_string(),
-            }
+        #[test]
+        fn test_scope() {
+            assert!(ApiKeyScope::Manage.allows(ApiKeyScope::Write));
+            assert!(ApiKeyScope::Write.allows(ApiKeyScope::Read));
+            assert!(ApiKeyScope::Read.allows(ApiKeyScope::Read));
+            assert!(!ApiKeyScope::Read.allows(ApiKeyScope::Write));
+            assert!(!ApiKeyScope::Write.allows(ApiKeyScope::Manage));
         }
     }
 }
 
-mod client_variable {
+pub mod channel_client_perm {
+    use super::{from_int_bool, from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
 
-    use super::{from_str, FromQueryString};
-    use crate::datastructures::ClientEdit;
-    use serde_derive::Deserialize;
+    // Only first record of reply carries cid and cldbid, so they are not kept here
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ChannelClientPerm {
+        permsid: String,
+        #[serde(deserialize_with = "from_str")]
+        permvalue: i64,
+        #[serde(default, deserialize_with = "from_int_bool")]
+        permnegated: bool,
+        #[serde(default, deserialize_with = "from_int_bool")]
+        permskip: bool,
+    }
 
-    #[derive(Clone, Debug, Default, Deserialize)]
-    pub struct ClientVariable {
-        #[serde(deserialize_with = "from_str", rename = "clid")]
-        client_id: i64,
-        #[serde(rename = "client_description")]
+    impl ChannelClientPerm {
+        pub fn permsid(&self) -> &str {
+            &self.permsid
+        }
+        pub fn permvalue(&self) -> i64 {
+            self.permvalue
+        }
+        pub fn permnegated(&self) -> bool {
+            self.permnegated
+        }
+        pub fn permskip(&self) -> bool {
+            self.permskip
+        }
+    }
+
+    impl FromQueryString for ChannelClientPerm {}
+}
+
+pub mod channel_perm {
+    use super::{from_int_bool, from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    // Only first record of reply carries cid
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ChannelPerm {
+        permsid: String,
+        #[serde(deserialize_with = "from_str")]
+        permvalue: i64,
+        #[serde(default, deserialize_with = "from_int_bool")]
+        permnegated: bool,
+        #[serde(default, deserialize_with = "from_int_bool")]
+        permskip: bool,
+    }
+
+    impl ChannelPerm {
+        pub fn permsid(&self) -> &str {
+            &self.permsid
+        }
+        pub fn permvalue(&self) -> i64 {
+            self.permvalue
+        }
+        pub fn permnegated(&self) -> bool {
+            self.permnegated
+        }
+        pub fn permskip(&self) -> bool {
+            self.permskip
+        }
+    }
+
+    impl FromQueryString for ChannelPerm {}
+}
+
+pub mod channel_group {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    // `type` of server and channel groups
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum GroupType {
+        Template,
+        Regular,
+        Query,
+        Unknown(i64),
+    }
+
+    impl From<i64> for GroupType {
+        fn from(value: i64) -> Self {
+            match value {
+                0 => Self::Template,
+                1 => Self::Regular,
+                2 => Self::Query,
+                _ => Self::Unknown(value),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ChannelGroup {
+        #[serde(deserialize_with = "from_str")]
+        cgid: i64,
+        #[serde(default)]
+        name: String,
+        // Regular if absent, as most groups are
+        #[serde(
+            default = "regular_type",
+            deserialize_with = "from_str",
+            rename = "type"
+        )]
+        group_type: i64,
+    }
+
+    pub(super) fn regular_type() -> i64 {
+        1
+    }
+
+    impl ChannelGroup {
+        pub fn cgid(&self) -> i64 {
+            self.cgid
+        }
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+        pub fn group_type(&self) -> i64 {
+            self.group_type
+        }
+        pub fn kind(&self) -> GroupType {
+            GroupType::from(self.group_type)
+        }
+    }
+
+    impl FromQueryString for ChannelGroup {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::channel_group::{ChannelGroup, GroupType};
+        use crate::datastructures::FromQueryString;
+
+        #[test]
+        fn test_minimal() {
+            let result = ChannelGroup::from_query("cgid=5").unwrap();
+            assert_eq!(result.cgid(), 5);
+            assert_eq!(result.name(), "");
+            assert_eq!(result.kind(), GroupType::Regular);
+        }
+    }
+}
+
+pub mod channel_group_id {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    // Group id returned by channelgroupadd and channelgroupcopy
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ChannelGroupId {
+        #[serde(deserialize_with = "from_str")]
+        cgid: i64,
+    }
+
+    impl ChannelGroupId {
+        pub fn cgid(&self) -> i64 {
+            self.cgid
+        }
+    }
+
+    impl FromQueryString for ChannelGroupId {}
+}
+
+pub mod channel_group_client {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ChannelGroupClient {
+        #[serde(deserialize_with = "from_str")]
+        cid: i64,
+        #[serde(deserialize_with = "from_str")]
+        cldbid: i64,
+        #[serde(deserialize_with = "from_str")]
+        cgid: i64,
+    }
+
+    impl ChannelGroupClient {
+        pub fn cid(&self) -> i64 {
+            self.cid
+        }
+        pub fn cldbid(&self) -> i64 {
+            self.cldbid
+        }
+        pub fn cgid(&self) -> i64 {
+            self.cgid
+        }
+    }
+
+    impl FromQueryString for ChannelGroupClient {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::channel_group_client::ChannelGroupClient;
+        use crate::datastructures::FromQueryString;
+
+        // Every field is part of the membership, none of them is optional
+        #[test]
+        fn test_minimal() {
+            let result = ChannelGroupClient::from_query("cid=2 cldbid=7 cgid=5").unwrap();
+            assert_eq!(result.cid(), 2);
+            assert_eq!(result.cldbid(), 7);
+            assert_eq!(result.cgid(), 5);
+            assert!(ChannelGroupClient::from_query("cid=2 cgid=5").is_err());
+        }
+    }
+}
+
+pub mod client {
+    use super::from_str;
+    use super::FromQueryString;
+    use serde_derive::{Deserialize, Serialize};
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum ClientType {
+        Regular,
+        Query,
+        Unknown(i64),
+    }
+
+    impl From<i64> for ClientType {
+        fn from(value: i64) -> Self {
+            match value {
+                0 => Self::Regular,
+                1 => Self::Query,
+                _ => Self::Unknown(value),
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+    pub struct Client {
+        #[serde(deserialize_with = "from_str")]
+        clid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        cid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        client_database_id: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        client_type: i64,
+        // Only present with `clientlist -uid`
+        #[serde(default)]
+        client_unique_identifier: String,
+        #[serde(default)]
+        client_nickname: String,
+        // Milliseconds, only present with `clientlist -times`
+        #[serde(default, deserialize_with = "from_str")]
+        client_idle_time: i64,
+        // Only present with `clientlist -ip`, and only if key may view client ip
+        #[serde(default, rename = "connection_client_ip")]
+        client_ip: String,
+        // Only present with `clientlist -voice`
+        #[serde(default, deserialize_with = "from_str")]
+        client_flag_talking: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        client_is_talker: i64,
+    }
+
+    #[allow(dead_code)]
+    impl Client {
+        pub fn client_id(&self) -> i64 {
+            self.clid
+        }
+        pub fn channel_id(&self) -> i64 {
+            self.cid
+        }
+        pub fn client_database_id(&self) -> i64 {
+            self.client_database_id
+        }
+        pub fn client_type(&self) -> i64 {
+            self.client_type
+        }
+        pub fn client_kind(&self) -> ClientType {
+            ClientType::from(self.client_type)
+        }
+        pub fn client_unique_identifier(&self) -> &str {
+            &self.client_unique_identifier
+        }
+        pub fn client_nickname(&self) -> &str {
+            &self.client_nickname
+        }
+        pub fn client_idle_time(&self) -> Duration {
+            Duration::from_millis(self.client_idle_time.max(0) as u64)
+        }
+        pub fn client_ip(&self) -> Option<IpAddr> {
+            self.client_ip.parse().ok()
+        }
+        pub fn is_talking(&self) -> bool {
+            self.client_flag_talking != 0
+        }
+        // Granted talk power in a moderated channel
+        pub fn is_talker(&self) -> bool {
+            self.client_is_talker != 0
+        }
+    }
+
+    impl FromQueryString for Client {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::client::{Client, ClientType};
+        use crate::datastructures::FromQueryString;
+
+        const TEST_STRING: &str = "clid=8 cid=1 client_database_id=1 client_nickname=serveradmin client_type=1 client_unique_identifier=serveradmin";
+
+        #[test]
+        fn test() {
+            let result = Client::from_query(TEST_STRING).unwrap();
+            assert_eq!(result.client_id(), 8);
+            assert_eq!(result.channel_id(), 1);
+            assert_eq!(result.client_database_id(), 1);
+            assert_eq!(result.client_nickname(), "serveradmin".to_string());
+            assert_eq!(result.client_type(), 1);
+            assert_eq!(result.client_kind(), ClientType::Query);
+            assert_eq!(result.client_unique_identifier(), "serveradmin".to_string());
+        }
+
+        #[test]
+        fn test_ip() {
+            let result = Client::from_query_multi(
+                "clid=8 connection_client_ip=192.0.2.7|clid=9 connection_client_ip=2001:db8::1|clid=10",
+            )
+            .unwrap();
+            assert_eq!(result[0].client_ip(), Some("192.0.2.7".parse().unwrap()));
+            assert_eq!(result[1].client_ip(), Some("2001:db8::1".parse().unwrap()));
+            assert_eq!(result[2].client_ip(), None);
+        }
+
+        #[test]
+        fn test_voice() {
+            let result = Client::from_query_multi(
+                "clid=8 client_flag_talking=1 client_is_talker=0|clid=9 client_flag_talking=0 client_is_talker=1|clid=10",
+            )
+            .unwrap();
+            assert!(result[0].is_talking());
+            assert!(!result[0].is_talker());
+            assert!(!result[1].is_talking());
+            assert!(result[1].is_talker());
+            assert!(!result[2].is_talking());
+        }
+
+        #[test]
+        fn test_minimal() {
+            let result = Client::from_query("clid=8").unwrap();
+            assert_eq!(result.client_id(), 8);
+            assert_eq!(result.client_database_id(), 0);
+            assert_eq!(result.client_unique_identifier(), "");
+        }
+
+        #[test]
+        fn test_multi() {
+            let result = Client::from_query_multi(
+                "clid=8 cid=1 client_database_id=1 client_nickname=serveradmin client_type=1|clid=9 cid=2 client_database_id=3 client_nickname=Some\\sOne client_type=0",
+            )
+            .unwrap();
+            assert_eq!(result.len(), 2);
+            assert_eq!(result[0].client_id(), 8);
+            assert_eq!(result[1].client_id(), 9);
+            assert_eq!(result[1].channel_id(), 2);
+            assert_eq!(result[1].client_nickname(), "Some One");
+        }
+
+        #[test]
+        fn test_serialize() {
+            let result = Client::from_query(TEST_STRING).unwrap();
+            let value = serde_json::to_value(&result).unwrap();
+            assert_eq!(value["clid"], 8);
+            assert_eq!(value["client_database_id"], 1);
+            assert_eq!(value["client_nickname"], "serveradmin");
+        }
+    }
+}
+
+pub mod client_db_find {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ClientDbFind {
+        #[serde(deserialize_with = "from_str")]
+        cldbid: i64,
+    }
+
+    impl ClientDbFind {
+        pub fn client_database_id(&self) -> i64 {
+            self.cldbid
+        }
+    }
+
+    impl FromQueryString for ClientDbFind {}
+}
+
+pub mod client_uid {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ClientUid {
+        #[serde(deserialize_with = "from_str")]
+        clid: i64,
+        // Server query names it cluid
+        #[serde(alias = "cluid")]
+        client_unique_identifier: String,
+    }
+
+    impl ClientUid {
+        pub fn client_id(&self) -> i64 {
+            self.clid
+        }
+        pub fn client_unique_identifier(&self) -> &str {
+            &self.client_unique_identifier
+        }
+    }
+
+    impl FromQueryString for ClientUid {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::client_uid::ClientUid;
+        use crate::datastructures::FromQueryString;
+
+        #[test]
+        fn test() {
+            let result =
+                ClientUid::from_query("clid=5 cluid=abc\\/def= nickname=Some\\sOne").unwrap();
+            assert_eq!(result.client_id(), 5);
+            assert_eq!(result.client_unique_identifier(), "abc/def=");
+        }
+    }
+}
+
+pub mod file_transfer {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+    use std::net::IpAddr;
+
+    // Reply of `ftinitupload` / `ftinitdownload`. Open a TCP connection to `port` on
+    // `host` (the query host if none) and write `ftkey` first, then send or read the
+    // file content.
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct FileTransfer {
+        #[serde(deserialize_with = "from_str")]
+        clientftfid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        serverftfid: i64,
+        #[serde(default)]
+        ftkey: String,
+        #[serde(default, deserialize_with = "from_str")]
+        port: u16,
+        // Comma separated addresses of the file transfer server, e.g. `0.0.0.0,::`
+        #[serde(default)]
+        ip: String,
+        // Only in download reply
+        #[serde(default, deserialize_with = "from_str")]
+        size: u64,
+        // Only in upload reply, offset to resume from
+        #[serde(default, deserialize_with = "from_str")]
+        seekpos: u64,
+        // Transfer is refused inside an ok reply, with its own status and msg
+        #[serde(default, deserialize_with = "from_str")]
+        status: i32,
+        #[serde(default)]
+        msg: String,
+    }
+
+    impl FileTransfer {
+        pub fn client_transfer_id(&self) -> i64 {
+            self.clientftfid
+        }
+        pub fn server_transfer_id(&self) -> i64 {
+            self.serverftfid
+        }
+        pub fn key(&self) -> &str {
+            &self.ftkey
+        }
+        pub fn port(&self) -> u16 {
+            self.port
+        }
+        // First address which can be connected to, unspecified ones mean the host
+        // the query connection goes to
+        pub fn host(&self) -> Option<&str> {
+            self.ip.split(',').map(str::trim).find(|ip| {
+                !ip.is_empty() && !ip.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified())
+            })
+        }
+        pub fn size(&self) -> u64 {
+            self.size
+        }
+        pub fn seek_position(&self) -> u64 {
+            self.seekpos
+        }
+        pub fn status(&self) -> i32 {
+            self.status
+        }
+        pub fn message(&self) -> &str {
+            &self.msg
+        }
+    }
+
+    impl FromQueryString for FileTransfer {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::file_transfer::FileTransfer;
+        use crate::datastructures::FromQueryString;
+
+        #[test]
+        fn test() {
+            let result = FileTransfer::from_query(
+                "clientftfid=1 serverftfid=7 ftkey=abcDEF port=30033 size=1024 proto=1",
+            )
+            .unwrap();
+            assert_eq!(result.client_transfer_id(), 1);
+            assert_eq!(result.key(), "abcDEF");
+            assert_eq!(result.port(), 30033);
+            assert_eq!(result.size(), 1024);
+            assert_eq!(result.status(), 0);
+            assert_eq!(result.host(), None);
+
+            let result =
+                FileTransfer::from_query("clientftfid=2 status=2050 msg=file\\snot\\sfound size=0")
+                    .unwrap();
+            assert_eq!(result.status(), 2050);
+            assert_eq!(result.message(), "file not found");
+        }
+
+        #[test]
+        fn test_host() {
+            for (ip, host) in [
+                ("0.0.0.0,::", None),
+                ("0.0.0.0,192.0.2.7", Some("192.0.2.7")),
+                ("ts.example.com", Some("ts.example.com")),
+                ("::1", Some("::1")),
+            ] {
+                let result =
+                    FileTransfer::from_query(&format!("clientftfid=1 port=30033 ip={}", ip))
+                        .unwrap();
+                assert_eq!(result.host(), host, "{}", ip);
+            }
+        }
+    }
+}
+
+pub mod log_entry {
+    use super::unescape;
+    use crate::datastructures::{QueryError, QueryResult};
+    use serde_derive::Serialize;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum LogLevel {
+        Error = 1,
+        Warning = 2,
+        Info = 3,
+        Debug = 4,
+    }
+
+    impl LogLevel {
+        pub fn value(&self) -> u8 {
+            *self as u8
+        }
+    }
+
+    impl TryFrom<u8> for LogLevel {
+        type Error = QueryError;
+
+        fn try_from(value: u8) -> Result<Self, QueryError> {
+            Ok(match value {
+                1 => LogLevel::Error,
+                2 => Self::Warning,
+                3 => Self::Info,
+                4 => Self::Debug,
+                _ => {
+                    return Err(QueryError::invalid_argument(&format!(
+                        "log level should be in 1..=4, got {}",
+                        value
+                    )))
+                }
+            })
+        }
+    }
+
+    // Not deserialized by serde, the `l=` value is a `|` separated line itself
+    #[derive(Clone, Debug, Default, Serialize)]
+    pub struct LogEntry {
+        timestamp: String,
+        level: String,
+        channel: String,
+        message: String,
+    }
+
+    impl LogEntry {
+        pub fn timestamp(&self) -> &str {
+            &self.timestamp
+        }
+        pub fn level(&self) -> &str {
+            &self.level
+        }
+        pub fn channel(&self) -> &str {
+            &self.channel
+        }
+        pub fn message(&self) -> &str {
+            &self.message
+        }
+
+        fn from_value(value: &str) -> QueryResult<Self> {
+            let line = unescape(value);
+            // timestamp|level|channel|server id|message, message may contain `|`
+            let mut fields = line.splitn(5, '|').map(str::trim);
+            match (
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+            ) {
+                (Some(timestamp), Some(level), Some(channel), Some(_), Some(message)) => Ok(Self {
+                    timestamp: timestamp.to_string(),
+                    level: level.to_string(),
+                    channel: channel.to_string(),
+                    message: message.to_string(),
+                }),
+                _ => Err(QueryError::parse_error(format!(
+                    "Unexpected log line: {:?}",
+                    line
+                ))),
+            }
+        }
+
+        // First record also carries `last_pos` and `file_size`, they are skipped
+        pub fn from_reply(line: &str) -> QueryResult<Vec<Self>> {
+            let mut ret = Vec::new();
+            for record in line.split('|') {
+                for pair in record.split(' ') {
+                    if let Some(value) = pair.strip_prefix("l=") {
+                        ret.push(Self::from_value(value)?);
+                    }
+                }
+            }
+            Ok(ret)
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::log_entry::{LogEntry, LogLevel};
+
+        #[test]
+        fn test() {
+            let entries = LogEntry::from_reply(
+                "last_pos=1024 file_size=2048 l=2023-01-01\\s12:00:00.000000\\pINFO\\s\\s\\s\\s\\pServerMain\\s\\s\\s\\p\\s\\s\\s\\pserver\\sstarted|\
+                l=2023-01-01\\s12:00:01.000000\\pWARNING\\pVirtualServer\\p1\\pa\\pb",
+            )
+            .unwrap();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0].timestamp(), "2023-01-01 12:00:00.000000");
+            assert_eq!(entries[0].level(), "INFO");
+            assert_eq!(entries[0].channel(), "ServerMain");
+            assert_eq!(entries[0].message(), "server started");
+            assert_eq!(entries[1].level(), "WARNING");
+            assert_eq!(entries[1].message(), "a|b");
+        }
+
+        #[test]
+        fn test_log_level() {
+            assert_eq!(LogLevel::try_from(4).unwrap(), LogLevel::Debug);
+            assert_eq!(LogLevel::Warning.value(), 2);
+            assert!(LogLevel::try_from(0).is_err());
+            assert!(LogLevel::try_from(5).is_err());
+        }
+    }
+}
+
+pub mod binding {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+    use std::net::IpAddr;
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct Binding {
+        #[serde(deserialize_with = "from_str")]
+        ip: IpAddr,
+    }
+
+    impl Binding {
+        pub fn ip(&self) -> IpAddr {
+            self.ip
+        }
+    }
+
+    impl FromQueryString for Binding {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::binding::Binding;
+        use crate::datastructures::FromQueryString;
+
+        #[test]
+        fn test() {
+            let result = Binding::from_query_multi("ip=0.0.0.0|ip=::").unwrap();
+            assert_eq!(result.len(), 2);
+            assert!(result[0].ip().is_unspecified());
+            assert!(result[1].ip().is_ipv6());
+        }
+    }
+}
+
+pub mod perm_reset {
+    use super::FromQueryString;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct PermReset {
+        token: String,
+    }
+
+    impl PermReset {
+        pub fn token(&self) -> &str {
+            &self.token
+        }
+    }
+
+    impl FromQueryString for PermReset {}
+}
+
+pub mod server_id {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ServerId {
+        #[serde(deserialize_with = "from_str")]
+        server_id: i64,
+    }
+
+    impl ServerId {
+        pub fn server_id(&self) -> i64 {
+            self.server_id
+        }
+    }
+
+    impl FromQueryString for ServerId {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::server_id::ServerId;
+        use crate::datastructures::FromQueryString;
+
+        #[test]
+        fn test() {
+            assert_eq!(ServerId::from_query("server_id=3").unwrap().server_id(), 3);
+        }
+    }
+}
+
+pub mod server_group {
+    use super::channel_group::regular_type;
+    use super::{from_str, FromQueryString, GroupType};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ServerGroup {
+        #[serde(deserialize_with = "from_str")]
+        sgid: i64,
+        #[serde(default)]
+        name: String,
+        // Regular if absent, same as channel group
+        #[serde(
+            default = "regular_type",
+            deserialize_with = "from_str",
+            rename = "type"
+        )]
+        group_type: i64,
+    }
+
+    impl ServerGroup {
+        pub fn sgid(&self) -> i64 {
+            self.sgid
+        }
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+        pub fn group_type(&self) -> i64 {
+            self.group_type
+        }
+        pub fn kind(&self) -> GroupType {
+            GroupType::from(self.group_type)
+        }
+    }
+
+    impl FromQueryString for ServerGroup {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::server_group::ServerGroup;
+        use crate::datastructures::{FromQueryString, GroupType};
+
+        #[test]
+        fn test() {
+            let result = ServerGroup::from_query_multi(
+                "sgid=1 name=Guest\\sQuery type=2 iconid=0 savedb=0|sgid=6 name=Server\\sAdmin type=1 iconid=300 savedb=1",
+            )
+            .unwrap();
+            assert_eq!(result[0].name(), "Guest Query");
+            assert_eq!(result[0].kind(), GroupType::Query);
+            assert_eq!(result[1].sgid(), 6);
+            assert_eq!(result[1].kind(), GroupType::Regular);
+        }
+
+        #[test]
+        fn test_minimal() {
+            let result = ServerGroup::from_query("sgid=6").unwrap();
+            assert_eq!(result.sgid(), 6);
+            assert_eq!(result.name(), "");
+            assert_eq!(result.kind(), GroupType::Regular);
+        }
+    }
+}
+
+pub mod server_group_id {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    // Group id returned by servergroupadd and servergroupcopy
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ServerGroupId {
+        #[serde(deserialize_with = "from_str")]
+        sgid: i64,
+    }
+
+    impl ServerGroupId {
+        pub fn sgid(&self) -> i64 {
+            self.sgid
+        }
+    }
+
+    impl FromQueryString for ServerGroupId {}
+}
+
+pub mod server_group_client {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    // Reply of `servergroupclientlist -names`
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ServerGroupClient {
+        #[serde(deserialize_with = "from_str")]
+        cldbid: i64,
+        #[serde(default)]
+        client_nickname: String,
+        #[serde(default)]
+        client_unique_identifier: String,
+    }
+
+    impl ServerGroupClient {
+        pub fn cldbid(&self) -> i64 {
+            self.cldbid
+        }
+        pub fn client_nickname(&self) -> &str {
+            &self.client_nickname
+        }
+        pub fn client_unique_identifier(&self) -> &str {
+            &self.client_unique_identifier
+        }
+    }
+
+    impl FromQueryString for ServerGroupClient {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::server_group_client::ServerGroupClient;
+        use crate::datastructures::FromQueryString;
+
+        #[test]
+        fn test() {
+            let result = ServerGroupClient::from_query_multi(
+                "cldbid=2 client_nickname=Some\\sOne client_unique_identifier=abc=|cldbid=5 client_nickname=Other client_unique_identifier=def=",
+            )
+            .unwrap();
+            assert_eq!(result.len(), 2);
+            assert_eq!(result[0].cldbid(), 2);
+            assert_eq!(result[0].client_nickname(), "Some One");
+            assert_eq!(result[1].client_unique_identifier(), "def=");
+        }
+    }
+}
+
+pub mod query_status {
+    use super::expand_escapes;
+    use crate::datastructures::{QueryError, QueryResult};
+    use anyhow::anyhow;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[allow(dead_code)]
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct QueryStatus {
+        id: i32,
+        msg: String,
+        // Additional context, e.g. remaining time of a flood ban
+        #[serde(default)]
+        extra_msg: Option<String>,
+    }
+
+    impl Default for QueryStatus {
+        fn default() -> Self {
+            Self {
+                id: 0,
+                msg: "ok".to_string(),
+                extra_msg: None,
+            }
+        }
+    }
+
+    impl QueryStatus {
+        pub fn new(id: i32, msg: &str) -> Self {
+            Self {
+                id,
+                msg: msg.to_string(),
+                extra_msg: None,
+            }
+        }
+        pub fn id(&self) -> i32 {
+            self.id
+        }
+        pub fn msg(&self) -> &String {
+            &self.msg
+        }
+        pub fn extra_msg(&self) -> Option<&str> {
+            self.extra_msg.as_deref()
+        }
+
+        pub fn into_err(self) -> QueryError {
+            QueryError::from(self)
+        }
+
+        pub fn into_result<T>(self, ret: T) -> QueryResult<T> {
+            if self.id == 0 {
+                return Ok(ret);
+            }
+            Err(self.into_err())
+        }
+    }
+
+    impl TryFrom<&str> for QueryStatus {
+        type Error = QueryError;
+
+        fn try_from(value: &str) -> Result<Self, Self::Error> {
+            let (_, line) = value
+                .split_once("error ")
+                .ok_or_else(|| anyhow!("Split error: {}", value))?;
+            serde_teamspeak_querystring::from_str(&expand_escapes(line))
+                .map_err(|e| QueryError::parse_error(format!("Parse {:?} error: {:?}", line, e)))
+        }
+    }
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::QueryStatus;
+
+        #[test]
+        fn test_extra_msg() {
+            let status = QueryStatus::try_from(
+                "error id=3329 msg=connection\\sfailed,\\syou\\sare\\sbanned extra_msg=you\\smay\\sretry\\sin\\s600\\sseconds",
+            )
+            .unwrap();
+            assert_eq!(status.extra_msg(), Some("you may retry in 600 seconds"));
+            let err = status.into_err();
+            assert_eq!(err.code(), 3329);
+            assert_eq!(err.extra_message(), Some("you may retry in 600 seconds"));
+
+            let status = QueryStatus::try_from("error id=0 msg=ok").unwrap();
+            assert_eq!(status.extra_msg(), None);
+        }
+    }
+}
+
+pub mod connect_info {
+    use super::FromQueryString;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct ConnectInfo {
+        ip: String,
+        port: u16,
+    }
+
+    impl ConnectInfo {
+        pub fn ip(&self) -> &str {
+            &self.ip
+        }
+        pub fn port(&self) -> u16 {
+            self.port
+        }
+
+        // Click to join link, e.g. ts3server://127.0.0.1?port=9987&cid=1
+        pub fn join_url(&self, channel_id: i64, password: Option<&str>) -> String {
+            let host = if self.ip.contains(':') && !self.ip.starts_with('[') {
+                format!("[{}]", self.ip)
+            } else {
+                self.ip.clone()
+            };
+            let mut url = format!("ts3server://{}?port={}&cid={}", host, self.port, channel_id);
+            if let Some(password) = password {
+                url.push_str("&password=");
+                for byte in password.bytes() {
+                    if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                        url.push(byte as char);
+                    } else {
+                        url.push_str(&format!("%{:02X}", byte));
+                    }
+                }
+            }
+            url
+        }
+    }
+
+    impl FromQueryString for ConnectInfo {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::connect_info::ConnectInfo;
+        use crate::datastructures::FromQueryString;
+
+        #[test]
+        fn test_join_url() {
+            let info = ConnectInfo::from_query("ip=127.0.0.1 port=9987").unwrap();
+            assert_eq!(
+                info.join_url(5, None),
+                "ts3server://127.0.0.1?port=9987&cid=5"
+            );
+            assert_eq!(
+                info.join_url(5, Some("p w&d")),
+                "ts3server://127.0.0.1?port=9987&cid=5&password=p%20w%26d"
+            );
+            let info = ConnectInfo::from_query("ip=::1 port=9987").unwrap();
+            assert_eq!(info.join_url(1, None), "ts3server://[::1]?port=9987&cid=1");
+        }
+
+        // Join url needs both, so neither is optional
+        #[test]
+        fn test_minimal() {
+            let info = ConnectInfo::from_query("ip=127.0.0.1 port=9987").unwrap();
+            assert_eq!(info.ip(), "127.0.0.1");
+            assert_eq!(info.port(), 9987);
+            assert!(ConnectInfo::from_query("port=9987").is_err());
+        }
+    }
+}
+
+pub mod server_connection_info {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    // Totals of the whole virtual server, from serverrequestconnectioninfo
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ServerConnectionInfo {
+        #[serde(default, deserialize_with = "from_str")]
+        connection_bandwidth_sent_last_second_total: u64,
+        #[serde(default, deserialize_with = "from_str")]
+        connection_bandwidth_received_last_second_total: u64,
+        #[serde(default, deserialize_with = "from_str")]
+        connection_packets_sent_total: u64,
+        #[serde(default, deserialize_with = "from_str")]
+        connection_packets_received_total: u64,
+        #[serde(default, deserialize_with = "from_str")]
+        connection_bytes_sent_total: u64,
+        #[serde(default, deserialize_with = "from_str")]
+        connection_bytes_received_total: u64,
+        #[serde(default, deserialize_with = "from_str")]
+        // Seconds, unlike connection_connected_time of clientinfo which is milliseconds
+        connection_connected_time: u64,
+    }
+
+    impl ServerConnectionInfo {
+        pub fn bandwidth_sent_last_second(&self) -> u64 {
+            self.connection_bandwidth_sent_last_second_total
+        }
+        pub fn bandwidth_received_last_second(&self) -> u64 {
+            self.connection_bandwidth_received_last_second_total
+        }
+        pub fn packets_sent_total(&self) -> u64 {
+            self.connection_packets_sent_total
+        }
+        pub fn packets_received_total(&self) -> u64 {
+            self.connection_packets_received_total
+        }
+        pub fn bytes_sent_total(&self) -> u64 {
+            self.connection_bytes_sent_total
+        }
+        pub fn bytes_received_total(&self) -> u64 {
+            self.connection_bytes_received_total
+        }
+        pub fn connected_time(&self) -> Duration {
+            Duration::from_secs(self.connection_connected_time)
+        }
+    }
+
+    impl FromQueryString for ServerConnectionInfo {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::server_connection_info::ServerConnectionInfo;
+        use crate::datastructures::FromQueryString;
+        use std::time::Duration;
+
+        #[test]
+        fn test() {
+            let result = ServerConnectionInfo::from_query(
+                "connection_bandwidth_sent_last_second_total=1200 connection_packets_sent_total=30 connection_bytes_received_total=4096 connection_connected_time=86400",
+            )
+            .unwrap();
+            assert_eq!(result.bandwidth_sent_last_second(), 1200);
+            assert_eq!(result.packets_sent_total(), 30);
+            assert_eq!(result.bytes_received_total(), 4096);
+            assert_eq!(result.bytes_sent_total(), 0);
+            assert_eq!(result.connected_time(), Duration::from_secs(86400));
+        }
+
+        #[test]
+        fn test_minimal() {
+            let result =
+                ServerConnectionInfo::from_query("connection_packets_sent_total=30").unwrap();
+            assert_eq!(result.packets_sent_total(), 30);
+            assert_eq!(result.bandwidth_received_last_second(), 0);
+            assert_eq!(result.connected_time(), Duration::ZERO);
+        }
+    }
+}
+
+pub mod temp_password {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct TempPassword {
+        // Creator
+        #[serde(default)]
+        nickname: String,
+        #[serde(default)]
+        desc: String,
+        #[serde(default)]
+        pw_clear: String,
+        // Unix timestamp
+        #[serde(deserialize_with = "from_str")]
+        end: i64,
+        // Channel the password joins into, 0 for default channel
+        #[serde(default, deserialize_with = "from_str")]
+        tcid: i64,
+    }
+
+    impl TempPassword {
+        pub fn nickname(&self) -> &str {
+            &self.nickname
+        }
+        pub fn desc(&self) -> &str {
+            &self.desc
+        }
+        pub fn password(&self) -> &str {
+            &self.pw_clear
+        }
+        pub fn end(&self) -> i64 {
+            self.end
+        }
+        pub fn target_channel_id(&self) -> i64 {
+            self.tcid
+        }
+    }
+
+    impl FromQueryString for TempPassword {}
+}
+
+pub mod virtual_server {
+    use super::{from_str, FromQueryString};
+    use serde_derive::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct VirtualServer {
+        #[serde(deserialize_with = "from_str")]
+        virtualserver_id: i64,
+        #[serde(deserialize_with = "from_str")]
+        virtualserver_port: u16,
+        #[serde(default)]
+        virtualserver_status: String,
+        // Offline servers do not report online clients
+        #[serde(default, deserialize_with = "from_str")]
+        virtualserver_clientsonline: i64,
+        #[serde(default)]
+        virtualserver_name: String,
+        // Seconds, offline servers do not report it
+        #[serde(default, deserialize_with = "from_str")]
+        virtualserver_uptime: u64,
+    }
+
+    impl VirtualServer {
+        pub fn id(&self) -> i64 {
+            self.virtualserver_id
+        }
+        pub fn port(&self) -> u16 {
+            self.virtualserver_port
+        }
+        pub fn status(&self) -> &str {
+            &self.virtualserver_status
+        }
+        pub fn clients_online(&self) -> i64 {
+            self.virtualserver_clientsonline
+        }
+        pub fn name(&self) -> &str {
+            &self.virtualserver_name
+        }
+        #[allow(dead_code)]
+        pub fn uptime(&self) -> Duration {
+            Duration::from_secs(self.virtualserver_uptime)
+        }
+    }
+
+    impl FromQueryString for VirtualServer {}
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::{format_duration, FromQueryString, VirtualServer};
+        use std::time::Duration;
+
+        #[test]
+        fn test_uptime() {
+            let result = VirtualServer::from_query_multi(
+                "virtualserver_id=1 virtualserver_port=9987 virtualserver_status=online virtualserver_uptime=93784|virtualserver_id=2 virtualserver_port=9988 virtualserver_status=offline",
+            )
+            .unwrap();
+            assert_eq!(result[0].uptime(), Duration::from_secs(93784));
+            assert_eq!(format_duration(result[0].uptime()), "1d 2h 3m 4s");
+            assert_eq!(format_duration(result[1].uptime()), "0s");
+            assert_eq!(format_duration(Duration::from_secs(3605)), "1h 0m 5s");
+        }
+
+        #[test]
+        fn test_minimal() {
+            let result =
+                VirtualServer::from_query("virtualserver_id=1 virtualserver_port=9987").unwrap();
+            assert_eq!(result.id(), 1);
+            assert_eq!(result.port(), 9987);
+            assert_eq!(result.status(), "");
+            assert_eq!(result.clients_online(), 0);
+            assert_eq!(result.name(), "");
+            assert_eq!(result.uptime(), Duration::ZERO);
+        }
+    }
+}
+
+pub mod notifies {
+    use super::{from_str, FromQueryString};
+    use crate::datastructures::QueryResult;
+    use serde_derive::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ClientEnterView {
+        #[serde(deserialize_with = "from_str")]
+        clid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        ctid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        reasonid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        client_database_id: i64,
+        #[serde(default)]
+        client_unique_identifier: String,
+        #[serde(default)]
+        client_nickname: String,
+        #[serde(default, deserialize_with = "from_str")]
+        client_type: i64,
+    }
+
+    impl ClientEnterView {
+        pub fn client_id(&self) -> i64 {
+            self.clid
+        }
+        pub fn channel_id(&self) -> i64 {
+            self.ctid
+        }
+        pub fn reason_id(&self) -> i64 {
+            self.reasonid
+        }
+        pub fn client_database_id(&self) -> i64 {
+            self.client_database_id
+        }
+        pub fn client_unique_identifier(&self) -> &str {
+            &self.client_unique_identifier
+        }
+        pub fn client_nickname(&self) -> &str {
+            &self.client_nickname
+        }
+        pub fn client_type(&self) -> i64 {
+            self.client_type
+        }
+    }
+
+    impl FromQueryString for ClientEnterView {}
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ClientLeftView {
+        #[serde(deserialize_with = "from_str")]
+        clid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        cfid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        ctid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        reasonid: i64,
+    }
+
+    impl ClientLeftView {
+        pub fn client_id(&self) -> i64 {
+            self.clid
+        }
+        pub fn origin_channel_id(&self) -> i64 {
+            self.cfid
+        }
+        pub fn target_channel_id(&self) -> i64 {
+            self.ctid
+        }
+        pub fn reason_id(&self) -> i64 {
+            self.reasonid
+        }
+    }
+
+    impl FromQueryString for ClientLeftView {}
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ClientMovedEvent {
+        #[serde(deserialize_with = "from_str")]
+        clid: i64,
+        #[serde(deserialize_with = "from_str")]
+        ctid: i64,
+        #[serde(deserialize_with = "from_str")]
+        reasonid: i64,
+        // Absent if client moved itself
+        #[serde(default, deserialize_with = "from_str")]
+        invokerid: i64,
+    }
+
+    impl ClientMovedEvent {
+        pub fn client_id(&self) -> i64 {
+            self.clid
+        }
+        pub fn target_channel_id(&self) -> i64 {
+            self.ctid
+        }
+        pub fn reason_id(&self) -> i64 {
+            self.reasonid
+        }
+        pub fn invoker_id(&self) -> i64 {
+            self.invokerid
+        }
+    }
+
+    impl FromQueryString for ClientMovedEvent {}
+
+    // Keys of notifychanneledited which are not channel properties
+    const CHANNEL_EDITED_META: [&str; 6] = [
+        "schandlerid",
+        "cid",
+        "reasonid",
+        "invokerid",
+        "invokername",
+        "invokeruid",
+    ];
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ChannelEdited {
+        #[serde(deserialize_with = "from_str")]
+        cid: i64,
+        #[serde(default, deserialize_with = "from_str")]
+        invokerid: i64,
+        #[serde(skip_deserializing)]
+        properties: HashMap<String, String>,
+    }
+
+    impl ChannelEdited {
+        pub fn channel_id(&self) -> i64 {
+            self.cid
+        }
+        pub fn invoker_id(&self) -> i64 {
+            self.invokerid
+        }
+        // Only changed properties are sent, e.g. `channel_name`, `channel_topic`
+        pub fn properties(&self) -> &HashMap<String, String> {
+            &self.properties
+        }
+
+        fn from_body(body: &str) -> QueryResult<Self> {
+            let mut edited = Self::from_query(body)?;
+            let mut properties = HashMap::<String, String>::from_query(body)?;
+            properties.retain(|key, _| !CHANNEL_EDITED_META.contains(&key.as_str()));
+            edited.properties = properties;
+            Ok(edited)
+        }
+    }
+
+    impl FromQueryString for ChannelEdited {}
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ChannelDescriptionChanged {
+        #[serde(deserialize_with = "from_str")]
+        cid: i64,
+    }
+
+    impl ChannelDescriptionChanged {
+        pub fn channel_id(&self) -> i64 {
+            self.cid
+        }
+    }
+
+    impl FromQueryString for ChannelDescriptionChanged {}
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct TextMessage {
+        // 1 private, 2 channel, 3 server
+        #[serde(deserialize_with = "from_str")]
+        targetmode: i64,
+        #[serde(default)]
+        msg: String,
+        #[serde(deserialize_with = "from_str")]
+        invokerid: i64,
+        #[serde(default)]
+        invokername: String,
+        #[serde(default)]
+        invokeruid: String,
+    }
+
+    impl TextMessage {
+        pub fn target_mode(&self) -> i64 {
+            self.targetmode
+        }
+        pub fn message(&self) -> &str {
+            &self.msg
+        }
+        pub fn invoker_id(&self) -> i64 {
+            self.invokerid
+        }
+        pub fn invoker_name(&self) -> &str {
+            &self.invokername
+        }
+        pub fn invoker_uid(&self) -> &str {
+            &self.invokeruid
+        }
+    }
+
+    impl FromQueryString for TextMessage {}
+
+    #[derive(Clone, Debug, Serialize)]
+    pub enum Notification {
+        ClientEnterView(ClientEnterView),
+        ClientLeftView(ClientLeftView),
+        ClientMoved(ClientMovedEvent),
+        ChannelEdited(ChannelEdited),
+        ChannelDescriptionChanged(ChannelDescriptionChanged),
+        TextMessage(TextMessage),
+        Other(String),
+    }
+
+    impl Notification {
+        pub fn is_notify(line: &str) -> bool {
+            line.starts_with("notify")
+        }
+
+        // Only the first record is decoded if server batches several into one line
+        pub fn from_line(line: &str) -> QueryResult<Self> {
+            let (name, body) = line.split_once(' ').unwrap_or((line, ""));
+            let body = body.split('|').next().unwrap_or_default();
+            Ok(match name {
+                "notifycliententerview" => {
+                    Self::ClientEnterView(ClientEnterView::from_query(body)?)
+                }
+                "notifyclientleftview" => Self::ClientLeftView(ClientLeftView::from_query(body)?),
+                "notifyclientmoved" => Self::ClientMoved(ClientMovedEvent::from_query(body)?),
+                "notifychanneledited" => Self::ChannelEdited(ChannelEdited::from_body(body)?),
+                "notifychanneldescriptionchanged" => {
+                    Self::ChannelDescriptionChanged(ChannelDescriptionChanged::from_query(body)?)
+                }
+                "notifytextmessage" => Self::TextMessage(TextMessage::from_query(body)?),
+                _ => Self::Other(line.to_string()),
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::notifies::{ClientEnterView, ClientLeftView};
+        use crate::datastructures::{FromQueryString, Notification};
+
+        const ENTER_VIEW: &str = "notifycliententerview schandlerid=1 cfid=0 ctid=1 reasonid=0 clid=5 client_unique_identifier=abc= client_nickname=Some\\sOne client_database_id=7 client_type=0";
+        const LEFT_VIEW: &str =
+            "notifyclientleftview schandlerid=1 cfid=1 ctid=0 reasonid=8 reasonmsg=bye clid=5";
+        const CHANNEL_EDITED: &str = "notifychanneledited schandlerid=1 cid=4 reasonid=10 invokerid=2 invokername=Admin invokeruid=xyz= channel_topic=new\\stopic";
+        const TEXT_MESSAGE: &str = "notifytextmessage schandlerid=1 targetmode=1 msg=!herd\\s5 target=1 invokerid=2 invokername=Admin invokeruid=xyz=";
+        const MOVED: &str = "notifyclientmoved schandlerid=1 ctid=3 reasonid=1 invokerid=2 invokername=Admin invokeruid=xyz= clid=5";
+
+        #[test]
+        fn test() {
+            match Notification::from_line(ENTER_VIEW).unwrap() {
+                Notification::ClientEnterView(view) => {
+                    assert_eq!(view.client_id(), 5);
+                    assert_eq!(view.channel_id(), 1);
+                    assert_eq!(view.client_database_id(), 7);
+                    assert_eq!(view.client_nickname(), "Some One");
+                    assert_eq!(view.client_unique_identifier(), "abc=");
+                }
+                n => panic!("Unexpected notification: {:?}", n),
+            }
+            match Notification::from_line(LEFT_VIEW).unwrap() {
+                Notification::ClientLeftView(view) => {
+                    assert_eq!(view.client_id(), 5);
+                    assert_eq!(view.origin_channel_id(), 1);
+                    assert_eq!(view.reason_id(), 8);
+                }
+                n => panic!("Unexpected notification: {:?}", n),
+            }
+            match Notification::from_line(MOVED).unwrap() {
+                Notification::ClientMoved(moved) => {
+                    assert_eq!(moved.client_id(), 5);
+                    assert_eq!(moved.target_channel_id(), 3);
+                    assert_eq!(moved.reason_id(), 1);
+                    assert_eq!(moved.invoker_id(), 2);
+                }
+                n => panic!("Unexpected notification: {:?}", n),
+            }
+            match Notification::from_line(CHANNEL_EDITED).unwrap() {
+                Notification::ChannelEdited(edited) => {
+                    assert_eq!(edited.channel_id(), 4);
+                    assert_eq!(edited.invoker_id(), 2);
+                    assert_eq!(edited.properties().len(), 1);
+                    assert_eq!(edited.properties()["channel_topic"], "new topic");
+                }
+                n => panic!("Unexpected notification: {:?}", n),
+            }
+            assert!(matches!(
+                Notification::from_line("notifychanneldescriptionchanged schandlerid=1 cid=4")
+                    .unwrap(),
+                Notification::ChannelDescriptionChanged(changed) if changed.channel_id() == 4
+            ));
+            assert!(matches!(
+                Notification::from_line(TEXT_MESSAGE).unwrap(),
+                Notification::TextMessage(message) if message.message() == "!herd 5" && message.invoker_name() == "Admin"
+            ));
+            assert!(matches!(
+                Notification::from_line("notifytalkstatuschange schandlerid=1 status=1").unwrap(),
+                Notification::Other(_)
+            ));
+        }
+
+        #[test]
+        fn test_minimal() {
+            let view = ClientEnterView::from_query("clid=5").unwrap();
+            assert_eq!(view.client_id(), 5);
+            assert_eq!(view.channel_id(), 0);
+            assert_eq!(view.client_unique_identifier(), "");
+            let view = ClientLeftView::from_query("clid=5").unwrap();
+            assert_eq!(view.client_id(), 5);
+            assert_eq!(view.origin_channel_id(), 0);
+            assert_eq!(view.reason_id(), 0);
+        }
+    }
+}
+
+mod status_result {
+    use crate::datastructures::QueryStatus;
+    use anyhow::Error;
+    use std::fmt::{Display, Formatter};
+    use std::future::Future;
+    use std::io::ErrorKind;
+    use std::pin::Pin;
+
+    pub type QueryResult<T> = Result<T, QueryError>;
+    // Query borrowing a connection or other context for `'a`, see `ConnectionManager::run`
+    pub type BoxedQuery<'a, T> = Pin<Box<dyn Future<Output = QueryResult<T>> + Send + 'a>>;
+
+    #[derive(Clone, Default, Debug)]
+    pub struct QueryError {
+        code: i32,
+        message: String,
+        // extra_msg of server error line
+        extra_message: Option<String>,
+    }
+
+    // Negative codes are raised locally, server never send negative id
+    impl QueryError {
+        pub const STATIC_EMPTY_RESPONSE: i32 = -1;
+        pub const INTERNAL_ERROR: i32 = -2;
+        pub const DATABASE_ID_ERROR: i32 = -3;
+        pub const STATUS_NOT_FOUND: i32 = -4;
+        pub const SPLIT_ERROR: i32 = -5;
+        pub const PARSE_ERROR: i32 = -6;
+        pub const RESULT_NOT_FOUND: i32 = -7;
+        pub const VARIABLE_NOT_FOUND: i32 = -8;
+        pub const CONNECTION_NOT_FOUND: i32 = -9;
+        pub const INVALID_ARGUMENT: i32 = -10;
+        pub const QUEUE_CLOSED: i32 = -11;
+        pub const TIMEOUT: i32 = -12;
+        pub const CONNECTION_CLOSED: i32 = -13;
+        pub const CHANNEL_NOT_FOUND: i32 = -14;
+        pub const AMBIGUOUS_CHANNEL: i32 = -15;
+        pub const INVALID_UTF8: i32 = -16;
+        pub const UNSUPPORTED_INTERFACE: i32 = -17;
+        pub const IO_ERROR: i32 = -18;
+        pub const IO_TIMED_OUT: i32 = -19;
+        pub const CONNECTION_RESET: i32 = -20;
+        pub const BROKEN_PIPE: i32 = -21;
+        pub const MOVE_NOT_APPLIED: i32 = -22;
+        pub const NOT_AUTHENTICATED: i32 = -23;
+        pub const AUTH_FAILED: i32 = -24;
+        pub const DRY_RUN: i32 = -25;
+
+        // Returned by ServerQuery `login` for a wrong name or password
+        pub const INVALID_LOGIN: i32 = 520;
+        pub const FLOODING: i32 = 524;
+        pub const INVALID_PARAMETER: i32 = 1538;
+        pub const NOT_LOGGED_IN: i32 = 1794;
+        // Returned by ClientQuery when the api key is not authorized
+        pub const CURRENTLY_NOT_POSSIBLE: i32 = 1796;
+        pub const PERMISSION_DENIED: i32 = 2568;
+    }
+
+    impl QueryError {
+        fn new(code: i32, message: String) -> Self {
+            Self {
+                code,
+                message,
+                extra_message: None,
+            }
+        }
+        pub fn internal_error(message: String) -> Self {
+            Self::new(Self::INTERNAL_ERROR, message)
+        }
+        pub fn static_empty_response() -> Self {
+            Self::new(
+                Self::STATIC_EMPTY_RESPONSE,
+                "Expect result but none found.".to_string(),
+            )
+        }
+        pub fn database_id_error() -> Self {
+            Self::new(
+                Self::DATABASE_ID_ERROR,
+                "Can't get self database_id".to_string(),
+            )
+        }
+        pub fn status_not_found() -> Self {
+            Self::new(Self::STATUS_NOT_FOUND, "Status line not found".to_string())
+        }
+        pub fn split_error(value: &str) -> Self {
+            Self::new(Self::SPLIT_ERROR, format!("Split error {}", value))
+        }
+        pub fn parse_error(message: String) -> Self {
+            Self::new(Self::PARSE_ERROR, message)
+        }
+        pub fn result_not_found(payload: &str) -> Self {
+            Self::new(
+                Self::RESULT_NOT_FOUND,
+                format!("Result not found: {:?}", payload),
+            )
+        }
+        pub fn variable_not_found(client_id: i64) -> Self {
+            Self::new(
+                Self::VARIABLE_NOT_FOUND,
+                format!("Client variable not found, client id: {}", client_id),
+            )
+        }
+        pub fn connection_not_found(name: &str) -> Self {
+            Self::new(
+                Self::CONNECTION_NOT_FOUND,
+                format!("Connection not found: {:?}", name),
+            )
+        }
+        pub fn invalid_argument(message: &str) -> Self {
+            Self::new(
+                Self::INVALID_ARGUMENT,
+                format!("Invalid argument: {}", message),
+            )
+        }
+        pub fn queue_closed() -> Self {
+            Self::new(Self::QUEUE_CLOSED, "Command queue is closed".to_string())
+        }
+        pub fn timeout() -> Self {
+            Self::new(Self::TIMEOUT, "Timed out waiting reply".to_string())
+        }
+        pub fn connection_closed() -> Self {
+            Self::new(
+                Self::CONNECTION_CLOSED,
+                "Connection closed while waiting reply".to_string(),
+            )
+        }
+        pub fn channel_not_found(name: &str) -> Self {
+            Self::new(
+                Self::CHANNEL_NOT_FOUND,
+                format!("No channel matches {:?}", name),
+            )
+        }
+        pub fn ambiguous_channel(name: &str, count: usize) -> Self {
+            Self::new(
+                Self::AMBIGUOUS_CHANNEL,
+                format!("{} channels match {:?}", count, name),
+            )
+        }
+        pub fn invalid_utf8() -> Self {
+            Self::new(
+                Self::INVALID_UTF8,
+                "Reply contains invalid UTF-8".to_string(),
+            )
+        }
+        pub fn move_not_applied(client_id: i64, channel_id: i64) -> Self {
+            Self::new(
+                Self::MOVE_NOT_APPLIED,
+                format!(
+                    "Client {} is not in channel {} after move",
+                    client_id, channel_id
+                ),
+            )
+        }
+        pub fn unsupported_interface(command: &str) -> Self {
+            Self::new(
+                Self::UNSUPPORTED_INTERFACE,
+                format!("{} is not supported on this interface", command),
+            )
+        }
+        pub fn not_authenticated(command: &str) -> Self {
+            Self::new(
+                Self::NOT_AUTHENTICATED,
+                format!("Not authenticated, call login first before {}", command),
+            )
+        }
+        pub fn auth_failed() -> Self {
+            Self::new(
+                Self::AUTH_FAILED,
+                "API key rejected, copy it again from Tools -> Options -> Addons -> ClientQuery"
+                    .to_string(),
+            )
+        }
+        pub fn login_failed() -> Self {
+            Self::new(
+                Self::AUTH_FAILED,
+                "Query login name or password rejected".to_string(),
+            )
+        }
+        pub fn dry_run(command: &str) -> Self {
+            Self::new(
+                Self::DRY_RUN,
+                format!("Dry run, {} was not sent so there is no result", command),
+            )
+        }
+        pub fn code(&self) -> i32 {
+            self.code
+        }
+        #[allow(dead_code)]
+        pub fn extra_message(&self) -> Option<&str> {
+            self.extra_message.as_deref()
+        }
+
+        pub fn is_not_logged_in(&self) -> bool {
+            self.code == Self::NOT_LOGGED_IN
+        }
+        pub fn is_flooding(&self) -> bool {
+            self.code == Self::FLOODING
+        }
+        pub fn is_permission_denied(&self) -> bool {
+            self.code == Self::PERMISSION_DENIED
+        }
+        pub fn is_parse_error(&self) -> bool {
+            self.code == Self::PARSE_ERROR
+        }
+        pub fn is_timeout(&self) -> bool {
+            self.code == Self::TIMEOUT
+        }
+        pub fn is_dry_run(&self) -> bool {
+            self.code == Self::DRY_RUN
+        }
+        // Server closed the socket (EOF), unlike timeout the connection is gone for sure
+        pub fn is_connection_closed(&self) -> bool {
+            self.code == Self::CONNECTION_CLOSED
+        }
+        // Socket level failure, reconnect is the only way out
+        pub fn is_io_error(&self) -> bool {
+            matches!(
+                self.code,
+                Self::IO_ERROR | Self::IO_TIMED_OUT | Self::CONNECTION_RESET | Self::BROKEN_PIPE
+            )
+        }
+        // Transient failures, sending the same command again on the same connection may succeed
+        pub fn is_retryable(&self) -> bool {
+            self.is_parse_error() || self.is_flooding() || self.is_timeout()
+        }
+    }
+
+    impl Display for QueryError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}({})", self.message, self.code)
+        }
+    }
+
+    impl std::error::Error for QueryError {}
+
+    impl From<QueryStatus> for QueryError {
+        fn from(status: QueryStatus) -> Self {
+            Self {
+                code: status.id(),
+                message: status.msg().clone(),
+                extra_message: status.extra_msg().map(str::to_string),
+            }
+        }
+    }
+
+    impl From<std::io::Error> for QueryError {
+        fn from(e: std::io::Error) -> Self {
+            let code = match e.kind() {
+                ErrorKind::TimedOut => Self::IO_TIMED_OUT,
+                ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => Self::CONNECTION_RESET,
+                ErrorKind::BrokenPipe => Self::BROKEN_PIPE,
+                ErrorKind::UnexpectedEof => Self::CONNECTION_CLOSED,
+                _ => Self::IO_ERROR,
+            };
+            Self::new(code, format!("IO error: {}", e))
+        }
+    }
+
+    impl From<Error> for QueryError {
+        fn from(s: Error) -> Self {
+            Self::internal_error(s.to_string())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::QueryError;
+        use std::io::{Error, ErrorKind};
+
+        #[test]
+        fn test_from_io_error() {
+            let err = QueryError::from(Error::from(ErrorKind::TimedOut));
+            assert_eq!(err.code(), QueryError::IO_TIMED_OUT);
+            let err = QueryError::from(Error::from(ErrorKind::ConnectionReset));
+            assert_eq!(err.code(), QueryError::CONNECTION_RESET);
+            let err = QueryError::from(Error::from(ErrorKind::BrokenPipe));
+            assert_eq!(err.code(), QueryError::BROKEN_PIPE);
+            let err = QueryError::from(Error::from(ErrorKind::PermissionDenied));
+            assert_eq!(err.code(), QueryError::IO_ERROR);
+            assert!(err.is_io_error());
+        }
+    }
+}
+
+mod client_variable {
+
+    use super::{from_str, FromQueryString};
+    use crate::datastructures::ClientEdit;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct ClientVariable {
+        #[serde(deserialize_with = "from_str", rename = "clid")]
+        client_id: i64,
+        // Empty descriptions come without value
+        #[serde(default, rename = "client_description")]
         description: String,
     }
 
     impl ClientVariable {
+        pub fn new(client_id: i64, description: String) -> Self {
+            Self {
+                client_id,
+                description,
+            }
+        }
         pub fn client_id(&self) -> i64 {
             self.client_id
         }
@@ -384,13 +2476,40 @@ mod client_edit {
     }
 }
 
+pub use api_key::{ApiKey, ApiKeyScope};
+pub use ban_add::BanAdd;
+pub use binding::Binding;
 pub use channel::Channel;
-pub use client::Client;
+pub use channel_client_perm::ChannelClientPerm;
+pub use channel_find::ChannelFind;
+pub use channel_group::{ChannelGroup, GroupType};
+pub use channel_group_client::ChannelGroupClient;
+pub use channel_group_id::ChannelGroupId;
+pub use channel_info::ChannelInfo;
+pub use channel_perm::ChannelPerm;
+pub use client::{Client, ClientType};
+pub use client_db_find::ClientDbFind;
 pub use client_edit::ClientEdit;
+pub use client_uid::ClientUid;
 pub use client_variable::ClientVariable;
 pub use connect_info::ConnectInfo;
+pub use connection_info::ConnectionInfo;
 pub use create_channel::CreateChannel;
+pub use file_transfer::FileTransfer;
+pub use log_entry::{LogEntry, LogLevel};
+pub use notifies::{Notification, TextMessage};
+pub use perm_reset::PermReset;
 pub use query_status::QueryStatus;
 use serde::Deserialize;
-pub use status_result::{QueryError, QueryResult};
+pub use server_connection_info::ServerConnectionInfo;
+pub use server_group::ServerGroup;
+pub use server_group_client::ServerGroupClient;
+pub use server_group_id::ServerGroupId;
+pub use server_id::ServerId;
+pub use status_result::{BoxedQuery, QueryError, QueryResult};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::Duration;
+pub use temp_password::TempPassword;
+pub use virtual_server::VirtualServer;
 pub use whoami::WhoAmI;
@@ -1,40 +1,315 @@
-use crate::datastructures::ClientVariable;
+use crate::configure::Config;
+use crate::datastructures::{ClientVariable, QueryError, QueryResult};
+use crate::retry::{retry, BoxedAttempt, RetryPolicy};
 use crate::socketlib::SocketConn;
-use anyhow::anyhow;
-use clap::{arg, Command};
-use log::info;
+use anyhow::{anyhow, Context};
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command};
+use log::{error, info, warn};
 use rand::distributions::{Distribution, Uniform};
+use std::fmt::{Display, Formatter};
 use std::time::Duration;
-use tokio::sync::oneshot::Receiver;
+use tokio::sync::watch;
+use tokio::time::Instant;
 
+mod bot;
+#[allow(dead_code)]
+mod chat;
+mod configure;
 #[allow(dead_code)]
 mod datastructures;
+#[allow(dead_code)]
+mod manager;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod retry;
+#[allow(dead_code)]
+mod roster;
+#[cfg(all(windows, feature = "service"))]
+mod service;
+#[allow(dead_code)]
+mod shared;
 mod socketlib;
+#[allow(dead_code)]
+mod stats;
+#[cfg(test)]
+mod testutil;
 
-async fn real_staff(
-    mut conn: SocketConn,
-    mut recv: Receiver<bool>,
-    variable: ClientVariable,
-) -> anyhow::Result<()> {
-    let database_id = conn
-        .query_database_id()
+const MAX_UPDATE_RETRIES: u32 = 3;
+const MAX_WHO_AM_I_RETRIES: u32 = 60;
+// Client may not be up yet right after boot
+const CONNECT_ATTEMPTS: u32 = 3;
+const CONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+// Not authorized and insufficient client permissions, retrying will not help
+const FATAL_ERROR_CODES: [i32; 2] = [
+    QueryError::CURRENTLY_NOT_POSSIBLE,
+    QueryError::PERMISSION_DENIED,
+];
+const API_KEY_ENV: &str = "TS3_API_KEY";
+
+// Process exit status, so wrapper scripts can tell why we stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(i32)]
+enum ExitCode {
+    Clean = 0,
+    Error = 1,
+    Auth = 2,
+    Connection = 3,
+    PermissionDenied = 4,
+    ForceExit = 137,
+}
+
+impl ExitCode {
+    fn from_query_error(e: &QueryError) -> Self {
+        match e.code() {
+            QueryError::NOT_LOGGED_IN
+            | QueryError::NOT_AUTHENTICATED
+            | QueryError::AUTH_FAILED
+            | QueryError::CURRENTLY_NOT_POSSIBLE => Self::Auth,
+            QueryError::PERMISSION_DENIED => Self::PermissionDenied,
+            _ if e.is_timeout() || e.is_connection_closed() || e.is_io_error() => Self::Connection,
+            _ => Self::Error,
+        }
+    }
+
+    // Set by `.context(ExitCode::...)`, otherwise guess from the query error inside
+    fn from_error(e: &anyhow::Error) -> Self {
+        if let Some(code) = e.downcast_ref::<Self>() {
+            return *code;
+        }
+        e.downcast_ref::<QueryError>()
+            .map(Self::from_query_error)
+            .unwrap_or(Self::Error)
+    }
+}
+
+impl Display for ExitCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Clean => "Clean shutdown",
+            Self::Error => "Error",
+            Self::Auth => "Authentication failed",
+            Self::Connection => "Connection failed",
+            Self::PermissionDenied => "Permission denied",
+            Self::ForceExit => "Force exit",
+        })
+    }
+}
+
+async fn exit_requested(exit: &mut watch::Receiver<bool>) {
+    while !*exit.borrow() {
+        // Sender is gone, nobody can ask us to stay
+        if exit.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+// Return true if exit was requested within duration
+async fn wait_exit(exit: &mut watch::Receiver<bool>, duration: Duration) -> bool {
+    tokio::time::timeout(duration, exit_requested(exit))
         .await
-        .map_err(|e| anyhow!("Got query database id error: {:?}", e))?;
+        .is_ok()
+}
 
+// Same as wait_exit, but ping server every keepalive seconds meanwhile
+async fn wait_next_round(
+    conn: &mut SocketConn,
+    exit: &mut watch::Receiver<bool>,
+    duration: Duration,
+    keepalive: Option<u64>,
+) -> bool {
+    let Some(keepalive) = keepalive else {
+        return wait_exit(exit, duration).await;
+    };
+    let deadline = Instant::now() + duration;
+    loop {
+        let remain = deadline.saturating_duration_since(Instant::now());
+        if remain.is_zero() {
+            return false;
+        }
+        if wait_exit(exit, remain.min(Duration::from_secs(keepalive))).await {
+            return true;
+        }
+        if Instant::now() < deadline {
+            if let Err(e) = conn.ping().await {
+                warn!("Keepalive ping failed: {}", e);
+            }
+        }
+    }
+}
+
+// Writes the database record (clientdbedit), so the description survives reconnects.
+// Fatal errors are returned at once, others after MAX_UPDATE_RETRIES attempts
+// or when exit is requested while waiting for next attempt.
+async fn update_description(
+    conn: &mut SocketConn,
+    exit: &mut watch::Receiver<bool>,
+    variable: &ClientVariable,
+    database_id: i64,
+) -> QueryResult<()> {
+    let mut retries = 0;
+    loop {
+        let Err(e) = conn
+            .update_client_description(variable.clone().into_edit(database_id))
+            .await
+        else {
+            return Ok(());
+        };
+        if FATAL_ERROR_CODES.contains(&e.code()) {
+            return Err(e);
+        }
+        retries += 1;
+        if retries > MAX_UPDATE_RETRIES {
+            return Err(e);
+        }
+        warn!(
+            "Update description failed ({}/{}), retry: {}",
+            retries, MAX_UPDATE_RETRIES, e
+        );
+        if wait_exit(exit, Duration::from_secs(retries as u64)).await {
+            return Err(e);
+        }
+    }
+}
+
+// Connect and wait until login took effect, None if exit was requested meanwhile
+async fn login(
+    config: &Config,
+    exit: &mut watch::Receiver<bool>,
+) -> anyhow::Result<Option<SocketConn>> {
+    let key = config
+        .api_key()
+        .ok_or_else(|| anyhow!("API key is required"))?;
+
+    let mut conn = SocketConn::connect_with_retry(
+        config.host(),
+        config.port(),
+        CONNECT_ATTEMPTS,
+        CONNECT_BACKOFF,
+    )
+    .await
+    .map_err(|e| anyhow!("Connect teamspeak console error: {:?}", e))
+    .context(ExitCode::Connection)?;
+    conn.set_dry_run(config.dry_run());
+    if let Some(login_timeout) = config.login_timeout() {
+        conn.set_login_timeout(Duration::from_secs(login_timeout));
+    }
+    if let Err(e) = conn.login(key.expose()).await {
+        // Client may go away during auth as well, only a rejected key is final
+        let code = ExitCode::from_query_error(&e);
+        return Err(e).context(code);
+    }
+
+    let mut retries = 0;
+    while let Err(e) = conn.who_am_i().await {
+        if !e.is_not_logged_in() {
+            let code = ExitCode::from_query_error(&e);
+            return Err(anyhow!("Got error while query whoami: {}", e)).context(code);
+        }
+        retries += 1;
+        if retries > MAX_WHO_AM_I_RETRIES {
+            return Err(anyhow!(
+                "Login did not take effect after {} retries: {}",
+                MAX_WHO_AM_I_RETRIES,
+                e
+            ))
+            .context(ExitCode::Auth);
+        }
+        if wait_exit(exit, Duration::from_secs(1)).await {
+            return Ok(None);
+        }
+    }
+    Ok(Some(conn))
+}
+
+fn is_connection_error(e: &anyhow::Error) -> bool {
+    ExitCode::from_error(e) == ExitCode::Connection
+}
+
+fn reconnect_policy() -> RetryPolicy<anyhow::Error> {
+    RetryPolicy::new(u32::MAX, RECONNECT_BACKOFF)
+        .max_backoff(MAX_RECONNECT_BACKOFF)
+        .retry_if(is_connection_error)
+}
+
+// Retry `connect` with backoff until it succeeds, exit is requested or the server
+// rejects us (retrying a revoked key will not help). Normally `connect` is `login`
+// with config and exit receiver as context.
+async fn reconnect<C, T, F>(
+    exit: &mut watch::Receiver<bool>,
+    policy: &RetryPolicy<anyhow::Error>,
+    context: &mut C,
+    connect: F,
+) -> anyhow::Result<Option<T>>
+where
+    F: for<'a> FnMut(&'a mut C) -> BoxedAttempt<'a, Option<T>, anyhow::Error>,
+{
+    tokio::select! {
+        ret = retry(policy, context, connect) => ret,
+        _ = exit_requested(exit) => Ok(None),
+    }
+}
+
+// Refresh description until exit, conn and database_id are replaced on reconnect
+async fn refresh_loop(
+    conn: &mut SocketConn,
+    exit: &mut watch::Receiver<bool>,
+    variable: &ClientVariable,
+    config: &Config,
+    database_id: &mut i64,
+) -> anyhow::Result<()> {
     let mut rng = rand::thread_rng();
-    let die = Uniform::from(50..70);
+    let die = Uniform::from(config.min_interval()..config.max_interval());
     loop {
-        if recv.try_recv().is_ok() {
+        if *exit.borrow() {
             info!("Exit!");
             return Ok(());
         }
 
-        conn.update_client_description(variable.clone().into_edit(database_id))
-            .await?;
+        if !conn.is_connected() {
+            warn!("Connection lost, reconnecting");
+            let policy = reconnect_policy();
+            let mut context = (config.clone(), exit.clone());
+            let reconnected = reconnect(exit, &policy, &mut context, |(config, exit)| {
+                Box::pin(login(config, exit))
+            });
+            *conn = match reconnected.await? {
+                Some(conn) => conn,
+                None => return Ok(()),
+            };
+            // Database id does not change, but server may be a different one now
+            *database_id = match conn.query_database_id().await {
+                Ok(database_id) => database_id,
+                Err(e) => {
+                    error!("Got query database id error after reconnect: {}", e);
+                    continue;
+                }
+            };
+            info!("Reconnected");
+        }
+
+        match update_description(conn, exit, variable, *database_id).await {
+            Ok(_) => {}
+            Err(e) if FATAL_ERROR_CODES.contains(&e.code()) => {
+                let code = ExitCode::from_query_error(&e);
+                return Err(anyhow!("Got fatal error while update description: {}", e))
+                    .context(code);
+            }
+            Err(_) if *exit.borrow() => return Ok(()),
+            // Reconnect right away instead of waiting for next round
+            Err(_) if !conn.is_connected() => continue,
+            Err(e) => error!("Update description failed, skip this round: {}", e),
+        }
 
-        if tokio::time::timeout(Duration::from_secs(die.sample(&mut rng)), &mut recv)
-            .await
-            .is_ok()
+        if wait_next_round(
+            conn,
+            exit,
+            Duration::from_secs(die.sample(&mut rng)),
+            config.keepalive(),
+        )
+        .await
         {
             break;
         }
@@ -42,93 +317,393 @@ async fn real_staff(
     Ok(())
 }
 
-async fn staff(key: String, server: &str, port: u16) -> anyhow::Result<()> {
-    let mut conn = SocketConn::connect(server, port)
+async fn real_staff(
+    mut conn: SocketConn,
+    mut exit: watch::Receiver<bool>,
+    variable: ClientVariable,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let mut database_id = conn
+        .query_database_id()
         .await
-        .map_err(|e| anyhow!("Connect teamspeak console error: {:?}", e))?;
-    conn.login(&key).await?;
+        .map_err(|e| anyhow!("Got query database id error: {:?}", e))?;
 
-    let (sender, mut receiver) = tokio::sync::oneshot::channel();
-    tokio::select! {
-        _ = async move {
-            tokio::signal::ctrl_c().await.unwrap();
-            info!("Recv SIGINT signal, send exit signal");
-            sender.send(true).unwrap();
+    let ret = refresh_loop(&mut conn, &mut exit, &variable, config, &mut database_id).await;
+    // Only on clean shutdown, a fatal error means we can not write anyway
+    if ret.is_ok() && config.restore() && conn.is_connected() {
+        match conn
+            .update_client_description(variable.into_edit(database_id))
+            .await
+        {
+            Ok(_) => info!("Original description restored"),
+            Err(e) => warn!("Restore description failed: {}", e),
+        }
+    }
+    ret
+}
+
+struct ExitSignal {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl ExitSignal {
+    fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            terminate: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .map_err(|e| anyhow!("Register SIGTERM handler error: {:?}", e))?,
+        })
+    }
+
+    async fn recv(&mut self) -> &'static str {
+        #[cfg(unix)]
+        return tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            // None means the signal driver is gone (runtime shutting down), not a signal
+            Some(_) = self.terminate.recv() => "SIGTERM",
+        };
+        #[cfg(not(unix))]
+        {
             tokio::signal::ctrl_c().await.unwrap();
-            info!("Recv SIGINT again, force exit.");
-            std::process::exit(137);
-        } => {
-            return Ok(())
-        }
-
-        _ = async {
-            while let Err(e) = conn.who_am_i().await {
-                if e.code() == 1794 {
-                    if tokio::time::timeout(Duration::from_secs(1), &mut receiver)
-                        .await
-                        .is_ok()
-                    {
-                        return Err(e);
-                    }
-                } else {
-                    return Err(e);
-                }
-            }
-            Ok(())
-        } => {
+            "SIGINT"
+        }
+    }
+}
+
+async fn handle_signal(mut signal: ExitSignal, exit: watch::Sender<bool>) {
+    // Not inside info!, log macros skip their arguments when the level is disabled
+    let name = signal.recv().await;
+    info!("Recv {} signal, send exit signal", name);
+    exit.send(true).ok();
+    let name = signal.recv().await;
+    info!("Recv {} again, force exit.", name);
+    std::process::exit(ExitCode::ForceExit as i32);
+}
 
+async fn staff(config: Config, mut exit: watch::Receiver<bool>) -> anyhow::Result<()> {
+    if let Some(listen) = config.metrics_listen() {
+        #[cfg(feature = "metrics")]
+        {
+            let serve = metrics::serve(listen.to_string(), config.clone(), exit.clone());
+            tokio::spawn(async move {
+                if let Err(e) = serve.await {
+                    error!("Metrics exporter error: {:?}", e);
+                }
+            });
         }
+        #[cfg(not(feature = "metrics"))]
+        warn!(
+            "Metrics exporter is not available in this build, ignore {}",
+            listen
+        );
     }
+    let Some(mut conn) = login(&config, &mut exit).await? else {
+        return Ok(());
+    };
+    let herd = config.herd_rule();
+    if herd.is_some() || !config.promote_rules().is_empty() {
+        return bot::event_staff(conn, exit, herd, config.promote_rules().to_vec()).await;
+    }
+
     let who_am_i = conn.who_am_i().await?;
     //conn.register_events().await??;
 
     let variable = conn.query_client_description(who_am_i.client_id()).await?;
-    let (sender, receiver) = tokio::sync::oneshot::channel();
-    //let keepalive_signal = Arc::new(Mutex::new(false));
-    tokio::select! {
-        _ = async move {
-            tokio::signal::ctrl_c().await.unwrap();
-            sender.send(true).unwrap();
-            info!("Recv SIGINT signal, send exit signal");
-            tokio::signal::ctrl_c().await.unwrap();
-            info!("Recv SIGINT again, force exit.");
-            std::process::exit(137);
-        } => {}
-        /*_ = async move {
-            loop {
-                tokio::time::sleep(Duration::from_secs(30)).await;
-                let mut i = keepalive_signal.lock().await;
-                *i = true;
-            }
-        } => {}*/
-        ret = real_staff(conn, receiver, variable) =>  {
-           ret?
+    if config.once() {
+        let database_id = conn
+            .query_database_id()
+            .await
+            .map_err(|e| anyhow!("Got query database id error: {:?}", e))?;
+        if let Err(e) = update_description(&mut conn, &mut exit, &variable, database_id).await {
+            let code = ExitCode::from_query_error(&e);
+            return Err(anyhow!("Update description failed: {}", e)).context(code);
         }
+        info!("Description updated");
+        conn.logout().await.ok();
+        return Ok(());
     }
+    real_staff(conn, exit, variable, &config).await
+}
 
-    Ok(())
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+// Foreground mode, exit on Ctrl-C (or SIGTERM)
+fn run(config: Config) -> anyhow::Result<()> {
+    runtime().block_on(async {
+        let (exit_sender, exit) = watch::channel(false);
+        tokio::spawn(handle_signal(ExitSignal::new()?, exit_sender));
+        staff(config, exit).await
+    })
+}
+
+// Positional argument first, then key file, then environment variable
+fn resolve_api_key(matches: &ArgMatches) -> anyhow::Result<Option<String>> {
+    if let Some(api_key) = matches.get_one::<String>("API_KEY") {
+        return Ok(Some(api_key.to_string()));
+    }
+    if let Some(path) = matches.get_one::<String>("api-key-file") {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Read api key file {} error: {:?}", path, e))?;
+        let api_key = content.trim_end_matches(['\r', '\n']);
+        if api_key.is_empty() {
+            return Err(anyhow!("Api key file {} is empty", path));
+        }
+        return Ok(Some(api_key.to_string()));
+    }
+    Ok(std::env::var(API_KEY_ENV)
+        .ok()
+        .filter(|api_key| !api_key.is_empty()))
+}
+
+// Command line arguments take precedence over config file
+fn build_config(matches: &ArgMatches) -> anyhow::Result<Config> {
+    let mut config = match matches.get_one::<String>("config") {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(api_key) = resolve_api_key(matches)? {
+        config.set_api_key(api_key);
+    }
+    if config.api_key().is_none() {
+        return Err(anyhow!(
+            "No API key provided, pass <API_KEY>, --api-key-file, {} or api_key in config file",
+            API_KEY_ENV
+        ));
+    }
+    if let Some(host) = matches.get_one::<String>("host") {
+        config.set_host(host.to_string());
+    }
+    if let Some(port) = matches.get_one::<u16>("port") {
+        config.set_port(*port);
+    }
+    if let Some(min_interval) = matches.get_one::<u64>("min-interval") {
+        config.set_min_interval(*min_interval);
+    }
+    if let Some(max_interval) = matches.get_one::<u64>("max-interval") {
+        config.set_max_interval(*max_interval);
+    }
+    if let Some(keepalive) = matches.get_one::<u64>("keepalive") {
+        config.set_keepalive(*keepalive);
+    }
+    if let Some(login_timeout) = matches.get_one::<u64>("login-timeout") {
+        config.set_login_timeout(*login_timeout);
+    }
+    if let Some(watch_channel) = matches.get_one::<i64>("watch-channel") {
+        config.set_watch_channel(*watch_channel);
+    }
+    if let Some(move_to) = matches.get_one::<i64>("move-to") {
+        config.set_move_to(*move_to);
+    }
+    if let Some(exempt_groups) = matches.get_many::<i64>("exempt-group") {
+        config.set_exempt_groups(exempt_groups.copied().collect());
+    }
+    if matches.get_flag("dry-run") {
+        config.set_dry_run(true);
+    }
+    if let Some(listen) = matches.get_one::<String>("metrics-listen") {
+        config.set_metrics_listen(listen.to_string());
+    }
+    if matches.get_flag("no-restore") {
+        config.set_restore(false);
+    }
+    if matches.get_flag("once") {
+        config.set_once(true);
+    }
+    config.validate()?;
+    Ok(config)
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() {
     let matches = Command::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
-        .args(&[arg!(<API_KEY> "Api key of client query")])
+        .args(&[
+            arg!([API_KEY] "Api key of client query"),
+            arg!(--"api-key-file" <PATH> "Read api key from file instead of argument")
+                .conflicts_with("API_KEY"),
+            arg!(--config <PATH> "Load options from TOML (or .json) file"),
+            arg!(--host <HOST> "Client query host (default: localhost)"),
+            arg!(--port <PORT> "Client query port (default: 25639)")
+                .value_parser(value_parser!(u16)),
+            arg!(--"min-interval" <SECONDS> "Minimum seconds between updates (default: 50)")
+                .value_parser(value_parser!(u64)),
+            arg!(--"max-interval" <SECONDS> "Maximum seconds between updates (default: 70)")
+                .value_parser(value_parser!(u64)),
+            arg!(--keepalive <SECONDS> "Ping server every SECONDS while waiting")
+                .value_parser(value_parser!(u64)),
+            arg!(--"login-timeout" <SECONDS> "Seconds to wait for auth reply (default: 30)")
+                .value_parser(value_parser!(u64)),
+            arg!(--"watch-channel" <CID> "Move clients entering this channel away instead of updating description")
+                .value_parser(value_parser!(i64)),
+            arg!(--"move-to" <CID> "Channel to move clients into, used with --watch-channel")
+                .value_parser(value_parser!(i64)),
+            arg!(--"exempt-group" <SGID> "Server group not moved by --watch-channel, can be repeated")
+                .value_parser(value_parser!(i64))
+                .action(ArgAction::Append),
+            arg!(--"dry-run" "Log commands which change something instead of sending them"),
+            arg!(--"metrics-listen" <ADDR> "Serve Prometheus metrics on ADDR (requires `metrics` feature)"),
+            arg!(--"no-restore" "Do not write original description back on shutdown"),
+            arg!(--once "Update description a single time and exit, for cron-driven setups"),
+            arg!(--service "Run as Windows service (requires `service` feature)"),
+        ])
         .get_matches();
 
     env_logger::Builder::from_default_env().init();
 
-    tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap()
-        .block_on(staff(
-            matches
-                .get_one("API_KEY")
-                .map(|s: &String| s.to_string())
-                .unwrap(),
-            "localhost",
-            25639,
-        ))?;
+    let code = match build_config(&matches).and_then(|config| {
+        if matches.get_flag("service") {
+            #[cfg(all(windows, feature = "service"))]
+            return service::run(config);
+            #[cfg(not(all(windows, feature = "service")))]
+            warn!("Service mode is not available in this build, run in foreground");
+        }
+        run(config)
+    }) {
+        Ok(_) => ExitCode::Clean,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from_error(&e)
+        }
+    };
+    std::process::exit(code as i32);
+}
 
-    Ok(())
+#[cfg(test)]
+mod test {
+    use crate::configure::Config;
+    use crate::datastructures::{QueryError, QueryStatus};
+    use crate::retry::RetryPolicy;
+    use crate::testutil;
+    use crate::{is_connection_error, login, reconnect, ExitCode};
+    use anyhow::{anyhow, Context};
+    use std::time::Duration;
+    use tokio::sync::watch;
+
+    fn policy() -> RetryPolicy<anyhow::Error> {
+        RetryPolicy::new(u32::MAX, Duration::from_millis(1))
+            .max_backoff(Duration::from_millis(4))
+            .retry_if(is_connection_error)
+    }
+
+    fn permission_denied() -> anyhow::Error {
+        QueryStatus::try_from("error id=2568 msg=insufficient\\sclient\\spermissions")
+            .and_then(|status| status.into_result(()))
+            .unwrap_err()
+            .into()
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let (_sender, mut exit) = watch::channel(false);
+        let mut calls = 0;
+        let ret = reconnect(&mut exit, &policy(), &mut calls, |calls| {
+            Box::pin(async move {
+                *calls += 1;
+                if *calls < 3 {
+                    return Err(anyhow!("connection refused")).context(ExitCode::Connection);
+                }
+                Ok(Some(*calls))
+            })
+        })
+        .await;
+        assert_eq!(ret.unwrap(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_fatal() {
+        let (_sender, mut exit) = watch::channel(false);
+        let mut calls = 0;
+        let ret: anyhow::Result<Option<()>> =
+            reconnect(&mut exit, &policy(), &mut calls, |calls| {
+                Box::pin(async move {
+                    *calls += 1;
+                    Err(permission_denied())
+                })
+            })
+            .await;
+        assert_eq!(
+            ExitCode::from_error(&ret.unwrap_err()),
+            ExitCode::PermissionDenied
+        );
+        assert_eq!(calls, 1);
+
+        calls = 0;
+        let ret: anyhow::Result<Option<()>> =
+            reconnect(&mut exit, &policy(), &mut calls, |calls| {
+                Box::pin(async move {
+                    *calls += 1;
+                    Err(anyhow!("key revoked")).context(ExitCode::Auth)
+                })
+            })
+            .await;
+        assert_eq!(ExitCode::from_error(&ret.unwrap_err()), ExitCode::Auth);
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_exit() {
+        let (sender, mut exit) = watch::channel(false);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            sender.send(true).unwrap();
+        });
+        let mut calls = 0;
+        let ret: anyhow::Result<Option<()>> =
+            reconnect(&mut exit, &policy(), &mut calls, |calls| {
+                Box::pin(async move {
+                    *calls += 1;
+                    Err(QueryError::connection_closed().into())
+                })
+            })
+            .await;
+        assert!(ret.unwrap().is_none());
+        assert!(calls > 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_auth_closed() {
+        // Client restarts while we authenticate, the first connection is closed
+        // without any reply
+        let port = testutil::scripted_sessions(
+            testutil::BANNER_AUTH,
+            vec![
+                vec![("auth apikey=key", "")],
+                vec![
+                    ("auth apikey=key", testutil::OK),
+                    ("whoami", testutil::WHOAMI),
+                ],
+            ],
+        )
+        .await;
+        let mut config = Config::default();
+        config.set_host("127.0.0.1".to_string());
+        config.set_port(port);
+        config.set_api_key("key".to_string());
+        let (_sender, mut exit) = watch::channel(false);
+        let mut context = (config, exit.clone());
+        let ret = reconnect(&mut exit, &policy(), &mut context, |(config, exit)| {
+            Box::pin(login(config, exit))
+        })
+        .await;
+        assert!(ret.unwrap().is_some());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(
+            ExitCode::from_query_error(&QueryError::from(std::io::Error::from(
+                std::io::ErrorKind::ConnectionReset
+            ))),
+            ExitCode::Connection
+        );
+        assert_eq!(
+            ExitCode::from_query_error(&QueryError::auth_failed()),
+            ExitCode::Auth
+        );
+    }
 }
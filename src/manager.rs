@@ -0,0 +1,322 @@
+use crate::configure::SecretKey;
+use crate::datastructures::{BoxedQuery, QueryError, QueryResult};
+use crate::socketlib::{ConnState, SocketConn};
+use crate::stats::ConnStats;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+#[derive(Clone, Debug)]
+pub struct ConnectConfig {
+    server: String,
+    port: u16,
+    api_key: SecretKey,
+}
+
+impl ConnectConfig {
+    pub fn new(server: &str, port: u16, api_key: SecretKey) -> Self {
+        Self {
+            server: server.to_string(),
+            port,
+            api_key,
+        }
+    }
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub async fn connect(&self) -> QueryResult<SocketConn> {
+        let mut conn = SocketConn::connect(&self.server, self.port).await?;
+        conn.login(self.api_key.expose()).await?;
+        Ok(conn)
+    }
+}
+
+struct ManagedConn {
+    config: ConnectConfig,
+    conn: Option<SocketConn>,
+    connects: u64,
+    // Changes of current connection are relayed here, see `subscribe_state`
+    state: Arc<watch::Sender<ConnState>>,
+    relay: Option<JoinHandle<()>>,
+}
+
+impl ManagedConn {
+    fn set_state(&self, state: ConnState) {
+        if *self.state.borrow() != state {
+            self.state.send_replace(state);
+        }
+    }
+
+    // Replaced connection must not report into state any more
+    fn drop_conn(&mut self) {
+        self.conn = None;
+        if let Some(relay) = self.relay.take() {
+            relay.abort();
+        }
+    }
+
+    fn relay_state(&mut self, conn: &SocketConn) {
+        let mut changes = conn.subscribe_state();
+        let state = self.state.clone();
+        state.send_replace(*changes.borrow());
+        self.relay = Some(tokio::spawn(async move {
+            while changes.changed().await.is_ok() {
+                let current = *changes.borrow();
+                if *state.borrow() != current {
+                    state.send_replace(current);
+                }
+            }
+        }));
+    }
+}
+
+#[derive(Default)]
+pub struct ConnectionManager {
+    connections: HashMap<String, ManagedConn>,
+}
+
+impl ConnectionManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: &str, config: ConnectConfig) {
+        self.connections.insert(
+            name.to_string(),
+            ManagedConn {
+                config,
+                conn: None,
+                connects: 0,
+                state: Arc::new(watch::channel(ConnState::Closed).0),
+                relay: None,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<SocketConn> {
+        let mut managed = self.connections.remove(name)?;
+        let conn = managed.conn.take();
+        managed.drop_conn();
+        managed.set_state(ConnState::Closed);
+        conn
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.connections.keys()
+    }
+
+    pub fn is_connected(&self, name: &str) -> bool {
+        self.connections
+            .get(name)
+            .map(|managed| managed.conn.is_some())
+            .unwrap_or(false)
+    }
+
+    /// Transitions of the named connection across reconnects: `Reconnecting` while
+    /// dialing, then whatever the new connection reports. A connection which is never
+    /// used stays `Closed`.
+    #[allow(dead_code)]
+    pub fn subscribe_state(&self, name: &str) -> Option<watch::Receiver<ConnState>> {
+        self.connections
+            .get(name)
+            .map(|managed| managed.state.subscribe())
+    }
+
+    fn managed(&mut self, name: &str) -> QueryResult<&mut ManagedConn> {
+        self.connections
+            .get_mut(name)
+            .ok_or_else(|| QueryError::connection_not_found(name))
+    }
+
+    pub async fn reconnect(&mut self, name: &str) -> QueryResult<()> {
+        let managed = self.managed(name)?;
+        managed.drop_conn();
+        managed.set_state(ConnState::Reconnecting);
+        info!(
+            "Connecting {} ({}:{})",
+            name,
+            managed.config.server(),
+            managed.config.port()
+        );
+        let conn = match managed.config.connect().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                managed.set_state(ConnState::Closed);
+                return Err(e);
+            }
+        };
+        managed.relay_state(&conn);
+        managed.conn.replace(conn);
+        managed.connects += 1;
+        Ok(())
+    }
+
+    pub fn stats(&self, name: &str) -> Option<ConnStats> {
+        let managed = self.connections.get(name)?;
+        let stats = managed.conn.as_ref()?.stats();
+        Some(stats.with_reconnects(managed.connects.saturating_sub(1)))
+    }
+
+    pub async fn run<T, F>(&mut self, name: &str, f: F) -> QueryResult<T>
+    where
+        F: for<'a> FnOnce(&'a mut SocketConn) -> BoxedQuery<'a, T>,
+    {
+        if self.managed(name)?.conn.is_none() {
+            self.reconnect(name).await?;
+        }
+        let managed = self.managed(name)?;
+        let ret = f(managed.conn.as_mut().unwrap()).await;
+        if let Err(ref e) = ret {
+            // Socket is gone or stuck, drop the connection so the next call dials it
+            // again. Other local errors (parse, argument, interface) leave it usable.
+            if e.is_connection_closed() || e.is_io_error() || e.is_timeout() {
+                warn!("Drop connection {} due to error: {}", name, e);
+                managed.drop_conn();
+                managed.set_state(ConnState::Closed);
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::SecretKey;
+    use crate::datastructures::QueryError;
+    use crate::manager::{ConnectConfig, ConnectionManager};
+    use crate::socketlib::ConnState;
+    use crate::testutil;
+
+    fn config(port: u16) -> ConnectConfig {
+        ConnectConfig::new("127.0.0.1", port, SecretKey::new("KEY".to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let port = testutil::scripted_sessions(
+            testutil::BANNER,
+            vec![
+                vec![
+                    ("auth apikey=KEY", testutil::OK),
+                    ("whoami", testutil::ERROR_PERMISSION),
+                ],
+                vec![
+                    ("auth apikey=KEY", testutil::OK),
+                    ("whoami", testutil::WHOAMI),
+                ],
+            ],
+        )
+        .await;
+        let mut manager = ConnectionManager::new();
+        manager.insert("main", config(port));
+        assert!(!manager.is_connected("main"));
+        assert_eq!(
+            manager
+                .run("other", |conn| Box::pin(conn.who_am_i()))
+                .await
+                .unwrap_err()
+                .code(),
+            QueryError::CONNECTION_NOT_FOUND
+        );
+
+        // Dialed on first use, server and local errors keep the connection
+        let e = manager
+            .run("main", |conn| Box::pin(conn.who_am_i()))
+            .await
+            .unwrap_err();
+        assert!(e.is_permission_denied());
+        let e = manager
+            .run("main", |conn| Box::pin(conn.use_server(1)))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), QueryError::UNSUPPORTED_INTERFACE);
+        assert!(manager.is_connected("main"));
+
+        // Script is done, peer closed the socket
+        let e = manager
+            .run("main", |conn| Box::pin(conn.who_am_i()))
+            .await
+            .unwrap_err();
+        assert!(e.is_connection_closed());
+        assert!(!manager.is_connected("main"));
+
+        let me = manager
+            .run("main", |conn| Box::pin(conn.who_am_i()))
+            .await
+            .unwrap();
+        assert_eq!(me.client_id(), 2);
+        assert_eq!(manager.stats("main").unwrap().reconnects(), 1);
+
+        assert!(manager.remove("main").is_some());
+        assert!(manager.remove("main").is_none());
+        assert_eq!(manager.names().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_failed() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![(
+                "auth apikey=KEY",
+                "error id=1796 msg=currently\\snot\\spossible\n\r",
+            )],
+        )
+        .await;
+        let mut manager = ConnectionManager::new();
+        manager.insert("main", config(port));
+        assert_eq!(
+            manager.reconnect("main").await.unwrap_err().code(),
+            QueryError::AUTH_FAILED
+        );
+        assert!(!manager.is_connected("main"));
+        // Listener is gone after its only session
+        assert!(manager.reconnect("main").await.unwrap_err().is_io_error());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_state() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                ("auth apikey=KEY", testutil::OK),
+                ("whoami", testutil::WHOAMI),
+            ],
+        )
+        .await;
+        let mut manager = ConnectionManager::new();
+        manager.insert("main", config(port));
+        assert!(manager.subscribe_state("other").is_none());
+        let mut state = manager.subscribe_state("main").unwrap();
+        assert_eq!(*state.borrow(), ConnState::Closed);
+        let changes = tokio::spawn(async move {
+            let mut changes = Vec::new();
+            while state.changed().await.is_ok() {
+                let current = *state.borrow();
+                changes.push(current);
+                if current == ConnState::Closed {
+                    break;
+                }
+            }
+            changes
+        });
+        manager
+            .run("main", |conn| Box::pin(conn.who_am_i()))
+            .await
+            .unwrap();
+        manager.remove("main");
+        assert_eq!(
+            changes.await.unwrap(),
+            [
+                ConnState::Reconnecting,
+                ConnState::Authenticated,
+                ConnState::Closed
+            ]
+        );
+    }
+}
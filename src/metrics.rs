@@ -0,0 +1,199 @@
+use crate::configure::Config;
+use crate::datastructures::{ConnectInfo, QueryResult};
+use crate::socketlib::SocketConn;
+use crate::stats::ConnStats;
+use anyhow::anyhow;
+use log::{info, warn};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+// What the client query interface can tell about the server the client is connected to,
+// serverlist and serverinfo are server query only
+struct Sample {
+    server: ConnectInfo,
+    clients_online: usize,
+}
+
+async fn sample(conn: &mut SocketConn) -> QueryResult<Sample> {
+    let server = conn.server_connect_info().await?;
+    let clients_online = conn.query_real_clients().await?.len();
+    Ok(Sample {
+        server,
+        clients_online,
+    })
+}
+
+// Prometheus text exposition format, see
+// https://prometheus.io/docs/instrumenting/exposition_formats/
+fn render(sample: Option<&Sample>, stats: &ConnStats) -> String {
+    let mut out = String::new();
+    writeln!(out, "# HELP ts3_up Whether last poll succeeded").unwrap();
+    writeln!(out, "# TYPE ts3_up gauge").unwrap();
+    writeln!(out, "ts3_up {}", sample.is_some() as u8).unwrap();
+    writeln!(
+        out,
+        "# HELP ts3_clients_online Clients online on connected server"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE ts3_clients_online gauge").unwrap();
+    if let Some(sample) = sample {
+        writeln!(
+            out,
+            "ts3_clients_online{{ip=\"{}\",port=\"{}\"}} {}",
+            sample.server.ip(),
+            sample.server.port(),
+            sample.clients_online
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "# HELP ts3_query_commands_total Commands sent by exporter"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE ts3_query_commands_total counter").unwrap();
+    writeln!(out, "ts3_query_commands_total {}", stats.commands_sent()).unwrap();
+    writeln!(
+        out,
+        "# HELP ts3_query_errors_total Commands failed by exporter"
+    )
+    .unwrap();
+    writeln!(out, "# TYPE ts3_query_errors_total counter").unwrap();
+    writeln!(out, "ts3_query_errors_total {}", stats.error_count()).unwrap();
+    out
+}
+
+// Exporter uses its own connection, so polling never interleaves with the bot
+async fn poll(config: Config, mut exit: watch::Receiver<bool>, metrics: Arc<Mutex<String>>) {
+    let mut conn: Option<SocketConn> = None;
+    loop {
+        if !conn.as_ref().is_some_and(SocketConn::is_connected) {
+            conn = match crate::login(&config, &mut exit).await {
+                Ok(Some(conn)) => Some(conn),
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Metrics connection failed: {:?}", e);
+                    None
+                }
+            };
+        }
+        let rendered = match conn.as_mut() {
+            Some(conn) => match sample(conn).await {
+                Ok(sample) => render(Some(&sample), &conn.stats()),
+                Err(e) => {
+                    warn!("Poll metrics failed: {}", e);
+                    render(None, &conn.stats())
+                }
+            },
+            None => render(None, &ConnStats::default()),
+        };
+        *metrics.lock().unwrap() = rendered;
+        if crate::wait_exit(&mut exit, POLL_INTERVAL).await {
+            return;
+        }
+    }
+}
+
+// Every request gets the metrics, path and method are not checked
+async fn respond(mut stream: TcpStream, body: String) {
+    let mut buffer = [0u8; 1024];
+    if stream.read(&mut buffer).await.is_err() {
+        return;
+    }
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await.ok();
+}
+
+pub async fn serve(
+    listen: String,
+    config: Config,
+    mut exit: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&listen)
+        .await
+        .map_err(|e| anyhow!("Bind metrics listener {} error: {:?}", listen, e))?;
+    info!("Serving metrics on {}", listen);
+    let metrics = Arc::new(Mutex::new(render(None, &ConnStats::default())));
+    tokio::spawn(poll(config, exit.clone(), metrics.clone()));
+    loop {
+        tokio::select! {
+            ret = exit.changed() => {
+                if ret.is_err() || *exit.borrow() {
+                    return Ok(());
+                }
+            }
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    let body = metrics.lock().unwrap().clone();
+                    tokio::spawn(respond(stream, body));
+                }
+                Err(e) => warn!("Accept metrics connection error: {:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::configure::Config;
+    use crate::metrics::{render, sample};
+    use crate::socketlib::{Interface, SocketConn};
+    use crate::stats::ConnStats;
+    use crate::testutil;
+    use tokio::sync::watch;
+
+    // Same path as `poll`: api key login on the client query interface, then sample
+    #[tokio::test]
+    async fn test_sample() {
+        let port = testutil::scripted_server(
+            testutil::BANNER_AUTH,
+            vec![
+                ("auth apikey=key", testutil::OK),
+                ("whoami", testutil::WHOAMI),
+                (
+                    "serverconnectinfo",
+                    "ip=127.0.0.1 port=9987\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("clientlist", testutil::CLIENTLIST),
+            ],
+        )
+        .await;
+        let mut config = Config::default();
+        config.set_host("127.0.0.1".to_string());
+        config.set_port(port);
+        config.set_api_key("key".to_string());
+        let (_sender, mut exit) = watch::channel(false);
+        let mut conn = crate::login(&config, &mut exit).await.unwrap().unwrap();
+        assert_eq!(conn.interface(), Interface::Client);
+        let sample = sample(&mut conn).await.unwrap();
+        let out = render(Some(&sample), &conn.stats());
+        assert!(out.contains("ts3_up 1\n"));
+        assert!(out.contains("ts3_clients_online{ip=\"127.0.0.1\",port=\"9987\"} 1\n"));
+        assert!(out.contains("ts3_query_commands_total 4\n"));
+    }
+
+    #[tokio::test]
+    async fn test_sample_not_connected() {
+        // Client is running, but not connected to any server
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![("serverconnectinfo", "error id=1794 msg=not\\sconnected\n\r")],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert!(sample(&mut conn).await.is_err());
+        let out = render(None, &ConnStats::default());
+        assert!(out.contains("ts3_up 0\n"));
+        assert!(!out.contains("ts3_clients_online{"));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::datastructures::QueryError;
+use log::warn;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+// Attempt borrowing the retry context for `'a`, `BoxedQuery` if `E` is `QueryError`
+pub type BoxedAttempt<'a, T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'a>>;
+
+#[derive(Debug)]
+pub struct RetryPolicy<E = QueryError> {
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    retryable: fn(&E) -> bool,
+}
+
+impl RetryPolicy {
+    // Delay doubles after every failed attempt, errors are retried by `QueryError::is_retryable`
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            backoff,
+            max_backoff: Duration::MAX,
+            retryable: QueryError::is_retryable,
+        }
+    }
+}
+
+impl<E> RetryPolicy<E> {
+    // Also picks the error type, e.g. `anyhow::Error` for operations beyond a single query
+    pub fn retry_if<R>(self, retryable: fn(&R) -> bool) -> RetryPolicy<R> {
+        RetryPolicy {
+            attempts: self.attempts,
+            backoff: self.backoff,
+            max_backoff: self.max_backoff,
+            retryable,
+        }
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+}
+
+// Run `op` until it succeeds, fails with an error the policy does not retry, or
+// attempts are used up. The context is handed to every attempt, so the future can
+// borrow it mutably, e.g. `retry(&policy, conn, |conn| Box::pin(conn.who_am_i()))`.
+pub async fn retry<C, T, E, F>(policy: &RetryPolicy<E>, context: &mut C, mut op: F) -> Result<T, E>
+where
+    C: ?Sized,
+    E: Display,
+    F: for<'a> FnMut(&'a mut C) -> BoxedAttempt<'a, T, E>,
+{
+    let mut delay = policy.backoff;
+    let mut attempt = 1;
+    loop {
+        match op(context).await {
+            Err(e) if attempt < policy.attempts && (policy.retryable)(&e) => {
+                warn!("Attempt {} failed, retry in {:?}: {}", attempt, delay, e);
+                tokio::time::sleep(delay).await;
+                delay = delay.saturating_mul(2).min(policy.max_backoff);
+                attempt += 1;
+            }
+            ret => return ret,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::datastructures::QueryError;
+    use crate::retry::{retry, RetryPolicy};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+        let mut calls = 0;
+        let ret = retry(&policy, &mut calls, |calls| {
+            Box::pin(async move {
+                *calls += 1;
+                if *calls < 3 {
+                    return Err(QueryError::parse_error("error id=abc".to_string()));
+                }
+                Ok(*calls)
+            })
+        })
+        .await;
+        assert_eq!(ret.unwrap(), 3);
+
+        // Used up attempts return last error
+        calls = 0;
+        let ret: Result<(), _> = retry(&policy, &mut calls, |calls| {
+            Box::pin(async move {
+                *calls += 1;
+                Err(QueryError::timeout())
+            })
+        })
+        .await;
+        assert!(ret.unwrap_err().is_timeout());
+        assert_eq!(calls, 3);
+
+        // Not retryable, given up at once
+        calls = 0;
+        let ret: Result<(), _> = retry(&policy, &mut calls, |calls| {
+            Box::pin(async move {
+                *calls += 1;
+                Err(QueryError::auth_failed())
+            })
+        })
+        .await;
+        assert_eq!(ret.unwrap_err().code(), QueryError::AUTH_FAILED);
+        assert_eq!(calls, 1);
+    }
+}
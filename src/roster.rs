@@ -0,0 +1,106 @@
+use crate::datastructures::{Client, QueryResult};
+use crate::socketlib::SocketConn;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RosterEvent {
+    Joined(Client),
+    Left(Client),
+}
+
+// Presence tracking by polling `clientlist`, for connections which can not
+// register for events. Clients are matched by client id, so moving between
+// channels is neither a join nor a leave.
+pub struct RosterWatcher {
+    interval: Duration,
+    known: Option<HashMap<i64, Client>>,
+    next_poll: Instant,
+}
+
+impl Default for RosterWatcher {
+    fn default() -> Self {
+        Self::new(DEFAULT_POLL_INTERVAL)
+    }
+}
+
+impl RosterWatcher {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            known: None,
+            next_poll: Instant::now(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    // First snapshot only sets the baseline, clients already online are not reported
+    pub fn diff(&mut self, clients: Vec<Client>) -> Vec<RosterEvent> {
+        let current = clients
+            .into_iter()
+            .map(|client| (client.client_id(), client))
+            .collect::<HashMap<_, _>>();
+        let Some(known) = self.known.replace(current) else {
+            return vec![];
+        };
+        let current = self.known.as_ref().unwrap();
+        let mut events = known
+            .iter()
+            .filter(|(client_id, _)| !current.contains_key(client_id))
+            .map(|(_, client)| RosterEvent::Left(client.clone()))
+            .chain(
+                current
+                    .iter()
+                    .filter(|(client_id, _)| !known.contains_key(client_id))
+                    .map(|(_, client)| RosterEvent::Joined(client.clone())),
+            )
+            .collect::<Vec<_>>();
+        events.sort_by_key(|event| match event {
+            RosterEvent::Joined(client) | RosterEvent::Left(client) => client.client_id(),
+        });
+        events
+    }
+
+    // Wait until next poll is due, then query and diff
+    pub async fn next(&mut self, conn: &mut SocketConn) -> QueryResult<Vec<RosterEvent>> {
+        tokio::time::sleep_until(self.next_poll).await;
+        self.next_poll = Instant::now() + self.interval;
+        let clients = conn.query_clients().await?;
+        Ok(self.diff(clients))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::datastructures::{Client, FromQueryString};
+    use crate::roster::{RosterEvent, RosterWatcher};
+
+    fn clients(query: &str) -> Vec<Client> {
+        Client::from_query_multi(query).unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut watcher = RosterWatcher::default();
+        assert!(watcher
+            .diff(clients("clid=1 cid=1|clid=2 cid=1"))
+            .is_empty());
+        // Client 2 moved, 1 left, 3 joined
+        let events = watcher.diff(clients("clid=2 cid=5|clid=3 cid=1"));
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], RosterEvent::Left(client) if client.client_id() == 1));
+        assert!(matches!(&events[1], RosterEvent::Joined(client) if client.client_id() == 3));
+        assert!(watcher
+            .diff(clients("clid=2 cid=1|clid=3 cid=1"))
+            .is_empty());
+    }
+}
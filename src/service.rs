@@ -0,0 +1,81 @@
+use crate::configure::Config;
+use anyhow::anyhow;
+use log::error;
+use std::ffi::OsString;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::watch;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::{define_windows_service, service_dispatcher};
+
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+
+// Service main is called by the dispatcher without our arguments, so the parsed
+// config is kept here.
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+// Block until the service is stopped, must be started by the service control manager
+pub fn run(config: Config) -> anyhow::Result<()> {
+    CONFIG
+        .set(config)
+        .map_err(|_| anyhow!("Service is already started"))?;
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .map_err(|e| anyhow!("Start service dispatcher error: {:?}", e))
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Service error: {:?}", e);
+    }
+}
+
+fn status(state: ServiceState, controls: ServiceControlAccept, code: u32) -> ServiceStatus {
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: controls,
+        exit_code: ServiceExitCode::ServiceSpecific(code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+fn run_service() -> anyhow::Result<()> {
+    let config = CONFIG
+        .get()
+        .cloned()
+        .ok_or_else(|| anyhow!("Service config is missing"))?;
+    let (exit_sender, exit) = watch::channel(false);
+
+    // Stop and shutdown have the same meaning as Ctrl-C in foreground mode
+    let handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            exit_sender.send(true).ok();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })
+    .map_err(|e| anyhow!("Register service control handler error: {:?}", e))?;
+
+    handle.set_service_status(status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        0,
+    ))?;
+    let result = crate::runtime().block_on(crate::staff(config, exit));
+    handle.set_service_status(status(
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        result
+            .as_ref()
+            .map_or_else(crate::ExitCode::from_error, |_| crate::ExitCode::Clean) as u32,
+    ))?;
+    result
+}
@@ -0,0 +1,172 @@
+use crate::datastructures::{
+    BoxedQuery, Client, ClientEdit, ClientVariable, QueryError, QueryResult, WhoAmI,
+};
+use crate::socketlib::SocketConn;
+use log::debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+const COMMAND_QUEUE_SIZE: usize = 32;
+
+type Job = Box<
+    dyn for<'a> FnOnce(&'a mut SocketConn) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> + Send,
+>;
+
+// Only the owner task touches the connection, callers enqueue a job with their own
+// reply channel, so concurrent commands never interleave on the socket.
+#[derive(Clone)]
+pub struct SharedConn {
+    queue: mpsc::Sender<Job>,
+}
+
+impl SharedConn {
+    pub fn new(conn: SocketConn) -> Self {
+        let (queue, receiver) = mpsc::channel(COMMAND_QUEUE_SIZE);
+        tokio::spawn(Self::owner(conn, receiver));
+        Self { queue }
+    }
+
+    async fn owner(mut conn: SocketConn, mut receiver: mpsc::Receiver<Job>) {
+        while let Some(job) = receiver.recv().await {
+            job(&mut conn).await;
+        }
+        debug!("All handles dropped, close shared connection");
+    }
+
+    pub async fn run<T, F>(&self, f: F) -> QueryResult<T>
+    where
+        T: Send + 'static,
+        F: for<'a> FnOnce(&'a mut SocketConn) -> BoxedQuery<'a, T> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let job: Job = Box::new(move |conn| {
+            Box::pin(async move {
+                // Caller may have given up already, nothing to do then
+                sender.send(f(conn).await).ok();
+            })
+        });
+        self.queue
+            .send(job)
+            .await
+            .map_err(|_| QueryError::queue_closed())?;
+        receiver.await.map_err(|_| QueryError::queue_closed())?
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+
+    pub async fn who_am_i(&self) -> QueryResult<WhoAmI> {
+        self.run(|conn| Box::pin(conn.who_am_i())).await
+    }
+
+    pub async fn ping(&self) -> QueryResult<Duration> {
+        self.run(|conn| Box::pin(conn.ping())).await
+    }
+
+    pub async fn logout(&self) -> QueryResult<()> {
+        self.run(|conn| Box::pin(conn.logout())).await
+    }
+
+    pub async fn query_clients(&self) -> QueryResult<Vec<Client>> {
+        self.run(|conn| Box::pin(conn.query_clients())).await
+    }
+
+    pub async fn query_database_id(&self) -> QueryResult<i64> {
+        self.run(|conn| Box::pin(conn.query_database_id())).await
+    }
+
+    pub async fn update_client_description(&self, edit_var: ClientEdit) -> QueryResult<()> {
+        self.run(|conn| Box::pin(conn.update_client_description(edit_var)))
+            .await
+    }
+
+    pub async fn query_client_description(&self, client_id: i64) -> QueryResult<ClientVariable> {
+        self.run(move |conn| Box::pin(conn.query_client_description(client_id)))
+            .await
+    }
+
+    pub async fn switch_channel(&self, channel_id: i64, verify: bool) -> QueryResult<()> {
+        self.run(move |conn| Box::pin(conn.switch_channel(channel_id, verify)))
+            .await
+    }
+
+    // Shared connection has no owner to cancel the subscription, so it is kept
+    pub async fn register_events(&self) -> QueryResult<()> {
+        self.run(|conn| Box::pin(async { conn.register_events().await.map(|s| s.keep()) }))
+            .await
+    }
+}
+
+impl From<SocketConn> for SharedConn {
+    fn from(conn: SocketConn) -> Self {
+        Self::new(conn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::shared::SharedConn;
+    use crate::socketlib::SocketConn;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // Reply each clientvariable request with the client id as description
+    async fn echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            writer
+                .write_all(b"TS3 Client\n\rWelcome to the TeamSpeak 3 ClientQuery interface\n\r")
+                .await
+                .unwrap();
+            let mut lines = BufReader::new(reader).lines();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let line = line.trim();
+                let Some(client_id) = line
+                    .strip_prefix("clientvariable clid=")
+                    .and_then(|s| s.split(' ').next())
+                else {
+                    continue;
+                };
+                writer
+                    .write_all(
+                        format!(
+                            "clid={0} client_description={0}\n\rerror id=0 msg=ok\n\r",
+                            client_id
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_concurrent() {
+        let port = echo_server().await;
+        let conn = SharedConn::new(SocketConn::connect("127.0.0.1", port).await.unwrap());
+
+        let tasks = (1..=8)
+            .map(|client_id| {
+                let conn = conn.clone();
+                tokio::spawn(async move {
+                    (
+                        client_id,
+                        conn.query_client_description(client_id).await.unwrap(),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            let (client_id, variable) = task.await.unwrap();
+            assert_eq!(variable.description(), client_id.to_string());
+        }
+    }
+}
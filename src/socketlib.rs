@@ -1,80 +1,587 @@
+// Logging in this module: error for protocol-fatal conditions, warn for recoverable
+// or retried ones, wire traffic at trace.
 use crate::datastructures::{
-    Client, ClientEdit, ClientVariable, ConnectInfo, QueryError, QueryResult, WhoAmI,
+    ApiKey, ApiKeyScope, BanAdd, Binding, Channel, ChannelClientPerm, ChannelFind, ChannelGroup,
+    ChannelGroupClient, ChannelGroupId, ChannelInfo, ChannelPerm, Client, ClientDbFind, ClientEdit,
+    ClientType, ClientUid, ClientVariable, ConnectInfo, ConnectionInfo, CreateChannel,
+    FileTransfer, GroupType, LogEntry, LogLevel, Notification, PermReset, QueryError, QueryResult,
+    ServerConnectionInfo, ServerGroup, ServerGroupClient, ServerGroupId, ServerId, TempPassword,
+    VirtualServer, WhoAmI,
 };
 use crate::datastructures::{FromQueryString, QueryStatus};
-use anyhow::anyhow;
-use log::{error, warn};
+use crate::retry::{retry, RetryPolicy};
+use crate::stats::{ConnStats, Counters};
+use log::{debug, error, info, trace, warn};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 const BUFFER_SIZE: usize = 512;
+const EVENT_CHANNEL_SIZE: usize = 64;
+// How long the reader waits for a record stream to take the next record
+const RECORD_FORWARD_TIMEOUT: Duration = Duration::from_secs(5);
+// Deadline of whole reply, a reply trickling in slowly can not hold a command forever
+const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+// Busy client may take a while to check api key
+const DEFAULT_LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+const BANNER_PREFIXES: [&str; 4] = [
+    "TS3",
+    "Welcome to the TeamSpeak",
+    "Use the \"auth\" command",
+    "selected schandlerid=",
+];
+// Accepted by client query before auth, or server query before login
+const AUTH_FREE_COMMANDS: [&str; 4] = ["auth", "login", "help", "quit"];
+
+// Commands still sent to server in dry run mode, they do not change anything
+const PASSTHROUGH_COMMANDS: [&str; 30] = [
+    "auth",
+    "login",
+    "use",
+    "whoami",
+    "clientnotifyregister",
+    "clientnotifyunregister",
+    "clientlist",
+    "channellist",
+    "clientvariable",
+    "clientinfo",
+    "clientdbfind",
+    "clientgetdbidfromuid",
+    "clientgetuidfromclid",
+    "channelfind",
+    "channelinfo",
+    "channelgrouplist",
+    "channelgroupclientlist",
+    "servergrouplist",
+    "channelclientpermlist",
+    "channelpermlist",
+    "serverconnectinfo",
+    "serverlist",
+    "serveridgetbyport",
+    "bindinglist",
+    "serverrequestconnectioninfo",
+    "servertemppasswordlist",
+    "apikeylist",
+    "logview",
+    "quit",
+    "version",
+];
+// Commands a `read` scope api key may send, they only query or select state of this session
+const READ_SCOPE_COMMANDS: [&str; 29] = [
+    "auth",
+    "help",
+    "quit",
+    "use",
+    "currentschandlerid",
+    "serverconnectionhandlerlist",
+    "whoami",
+    "clientnotifyregister",
+    "clientnotifyunregister",
+    "clientlist",
+    "channellist",
+    "clientvariable",
+    "channelvariable",
+    "servervariable",
+    "clientdbfind",
+    "clientgetids",
+    "clientgetdbidfromuid",
+    "clientgetuidfromclid",
+    "channelfind",
+    "channelconnectinfo",
+    "channelgrouplist",
+    "channelgroupclientlist",
+    "servergrouplist",
+    "servergroupclientlist",
+    "channelclientpermlist",
+    "channelpermlist",
+    "serverconnectinfo",
+    "banlist",
+    "version",
+];
+const TERMINATOR: &str = "\n\r";
+const DRY_RUN_REPLY: &str = "error id=0 msg=ok\n";
+// Retries of a single message rejected by flood protection
+const MAX_FLOOD_RETRIES: u32 = 3;
+// Server limit of channel_name, in characters not bytes
+const MAX_CHANNEL_NAME_LENGTH: usize = 40;
+const DATABASE_ID_RETRY_DELAY: Duration = Duration::from_millis(200);
+// Returned by channelcreate if channel with same name exists
+const CHANNEL_NAME_IN_USE: i32 = 771;
+// Returned by clientmove if client is in the channel already
+const ALREADY_MEMBER_OF_CHANNEL: i32 = 770;
+// First key of every record these commands return, a reply starting otherwise
+// belongs to some other command
+const REPLY_MARKERS: [(&str, &str); 3] = [
+    ("clientlist", "clid="),
+    ("channellist", "cid="),
+    ("serverlist", "virtualserver_id="),
+];
+
+// Server query has no request id, replies come back in the order commands were
+// written. A command pushes its slot before writing, the reader pops the front slot
+// on every status line, so slots keep the exact write order even when several
+// commands are in flight. A slot abandoned by timeout still swallows its late reply.
+// Reply slot may carry a marker from `REPLY_MARKERS`, a stray reply which does not
+// contain it is dropped and the slot keeps waiting for the next one.
+enum Pending {
+    Reply(oneshot::Sender<QueryResult<String>>, Option<&'static str>),
+    // Records are forwarded one by one as they arrive, status line comes last
+    Records(mpsc::Sender<String>),
+}
+
+type PendingReplies = Arc<Mutex<VecDeque<Pending>>>;
+
+// Receivers of notifies next to the event stream, for helpers which wait for a single
+// event without taking the stream from its consumer. Closed ones are dropped.
+type Subscribers = Arc<Mutex<Vec<mpsc::Sender<Notification>>>>;
+
+// Last reply of a list command and when it was fetched
+type CachedList<T> = Option<(Instant, Vec<T>)>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnState {
+    // Banner asked for auth or came from server query, login is not done yet
+    Connected,
+    // Also the initial state if there is no login to do, or no banner to tell
+    Authenticated,
+    // Only reported by ConnectionManager, a single connection never reconnects itself
+    Reconnecting,
+    // After quit, or the server closed the socket
+    Closed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interface {
+    // Client query plugin, banner is "TS3 Client"
+    Client,
+    // Server query, banner is "TS3"
+    Server,
+    Unknown,
+}
+
+impl Interface {
+    fn from_banner(banner: &str) -> Self {
+        match banner.lines().next().map(str::trim) {
+            Some("TS3 Client") => Self::Client,
+            Some("TS3") => Self::Server,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+// Returned by `register_events`. Async drop is not available, so the guard has to be
+// cancelled explicitly. If it is dropped instead, unregister is sent in front of the
+// next command of the connection.
+#[must_use = "events stay registered until the subscription is cancelled"]
+pub struct EventSubscription {
+    unregister: Option<Arc<AtomicBool>>,
+}
+
+impl EventSubscription {
+    #[allow(dead_code)]
+    pub async fn cancel(mut self, conn: &mut SocketConn) -> QueryResult<()> {
+        self.unregister.take();
+        conn.unregister_events().await
+    }
+
+    // Keep events registered for the rest of the connection
+    pub fn keep(mut self) {
+        self.unregister.take();
+    }
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        if let Some(unregister) = self.unregister.take() {
+            unregister.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct RecordStream<T> {
+    receiver: mpsc::Receiver<String>,
+    timeout: Duration,
+    done: bool,
+    _marker: PhantomData<T>,
+}
+
+#[allow(dead_code)]
+impl<T: FromQueryString + Sized> RecordStream<T> {
+    // Return None once status line received, error status is returned as last item
+    pub async fn next(&mut self) -> Option<QueryResult<T>> {
+        if self.done {
+            return None;
+        }
+        let record = match tokio::time::timeout(self.timeout, self.receiver.recv()).await {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                self.done = true;
+                return Some(Err(QueryError::connection_closed()));
+            }
+            Err(_) => {
+                self.done = true;
+                return Some(Err(QueryError::timeout()));
+            }
+        };
+        if record.starts_with("error ") {
+            self.done = true;
+            return QueryStatus::try_from(record.as_str())
+                .and_then(|status| status.into_result(()))
+                .err()
+                .map(Err);
+        }
+        Some(T::from_query(&record))
+    }
+}
 
 pub struct SocketConn {
-    conn: TcpStream,
+    writer: OwnedWriteHalf,
+    pending: PendingReplies,
+    events: Option<mpsc::Receiver<QueryResult<Notification>>>,
+    subscribers: Subscribers,
+    reader: JoinHandle<()>,
+    counters: Arc<Counters>,
+    strict_utf8: Arc<AtomicBool>,
+    // Set by dropped EventSubscription
+    unregister_pending: Arc<AtomicBool>,
+    // Events are registered for this connection, by anyone
+    events_registered: bool,
+    dry_run: bool,
+    reply_timeout: Duration,
+    login_timeout: Duration,
+    server: String,
+    port: u16,
+    interface: Interface,
+    // Also set to Closed by reader task once the socket is closed
+    state: Arc<watch::Sender<ConnState>>,
+    // Disabled if None, see `set_list_cache_ttl`
+    list_cache_ttl: Option<Duration>,
+    client_cache: CachedList<Client>,
+    channel_cache: CachedList<Channel>,
+    // clientftfid of next file transfer, only has to be unique per connection
+    next_transfer_id: i64,
+}
+
+impl Drop for SocketConn {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
 }
 
 impl SocketConn {
-    async fn read_data(&mut self) -> anyhow::Result<Option<String>> {
+    async fn read_banner(conn: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
         let mut buffer = [0u8; BUFFER_SIZE];
-        let mut ret = String::new();
+        let mut ret = Vec::new();
         loop {
             let size = if let Ok(data) =
-                tokio::time::timeout(Duration::from_secs(2), self.conn.read(&mut buffer)).await
+                tokio::time::timeout(Duration::from_secs(2), conn.read(&mut buffer)).await
             {
-                match data {
-                    Ok(size) => size,
-                    Err(e) => return Err(anyhow!("Got error while read data: {:?}", e)),
-                }
+                data?
             } else {
                 return Ok(None);
             };
+            // Accepted and dropped, e.g. client still starting up, worth another attempt
+            if size == 0 && ret.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionAborted,
+                    "Connection closed before banner",
+                ));
+            }
 
-            ret.push_str(&String::from_utf8_lossy(&buffer[..size]));
-            if size < BUFFER_SIZE
-                || (ret
-                    .lines()
-                    .into_iter()
-                    .any(|line| line.starts_with("error id=")))
-            {
+            ret.extend_from_slice(&buffer[..size]);
+            if size < BUFFER_SIZE {
                 break;
             }
         }
         Ok(Some(ret))
     }
 
-    async fn write_data(&mut self, payload: &str) -> anyhow::Result<()> {
-        debug_assert!(payload.ends_with("\n\r"));
-        self.conn
-            .write(payload.as_bytes())
+    // Banner may arrive together with notify lines, split them so the rest
+    // can be handled by read loop
+    fn split_banner(mut data: Vec<u8>) -> (String, Vec<u8>) {
+        let mut end = 0;
+        while let Some(pos) = data[end..].iter().position(|c| *c == b'\n') {
+            let line = String::from_utf8_lossy(&data[end..end + pos + 1]);
+            let line = line.trim();
+            if !line.is_empty() && !Self::is_banner(line) {
+                break;
+            }
+            end += pos + 1;
+            if data.get(end) == Some(&b'\r') {
+                end += 1;
+            }
+        }
+        let rest = data.split_off(end);
+        (String::from_utf8_lossy(&data).into_owned(), rest)
+    }
+
+    fn front_records(pending: &PendingReplies) -> Option<mpsc::Sender<String>> {
+        match pending.lock().unwrap().front() {
+            Some(Pending::Records(sender)) => Some(sender.clone()),
+            _ => None,
+        }
+    }
+
+    // Keep consuming even if receiver is gone, status line still need to be popped.
+    // False if the stream was not drained in time.
+    async fn send_record(sender: &mpsc::Sender<String>, record: &str) -> bool {
+        tokio::time::timeout(RECORD_FORWARD_TIMEOUT, sender.send(record.to_string()))
             .await
-            .map(|size| {
-                if size != payload.as_bytes().len() {
-                    error!(
-                        "Error payload size mismatch! expect {} but {} found. payload: {:?}",
-                        payload.as_bytes().len(),
-                        size,
-                        payload
-                    )
+            .is_ok()
+    }
+
+    // Reader must not wait on a stream nobody drains, it would hold up every other
+    // reply and notify. A stalled stream is cut off, its consumer gets connection
+    // closed after the records already buffered.
+    async fn forward_records(pending: &PendingReplies, sender: &mpsc::Sender<String>, line: &str) {
+        for record in line.split('|').filter(|record| !record.is_empty()) {
+            if !Self::send_record(sender, record).await {
+                warn!("Record stream is not drained, abandon it");
+                if let Some(Pending::Records(sender)) = pending.lock().unwrap().front_mut() {
+                    // Receiver is dropped at once, later sends fail without waiting
+                    *sender = mpsc::channel(1).0;
                 }
-            })
-            .map_err(|e| anyhow!("Got error while send data: {:?}", e))?;
+                return;
+            }
+        }
+    }
+
+    // A partial line can be streamed if it is neither notify nor status line
+    fn is_partial_records(partial: &[u8]) -> bool {
+        let start = partial
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .unwrap_or(partial.len());
+        let partial = &partial[start..];
+        !partial.starts_with(b"notify") && !partial.starts_with(b"error ")
+    }
+
+    // Route every line server sent: notifies go to events channel and subscribers,
+    // other lines are collected until status line and handed to the earliest waiting
+    // command.
+    async fn read_loop<R: AsyncRead + Unpin>(
+        mut reader: R,
+        pending: PendingReplies,
+        events: mpsc::Sender<QueryResult<Notification>>,
+        subscribers: Subscribers,
+        counters: Arc<Counters>,
+        strict_utf8: Arc<AtomicBool>,
+        mut received: Vec<u8>,
+    ) {
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let mut reply = String::new();
+        // Some line of current reply was not valid UTF-8, only tracked in strict mode
+        let mut reply_invalid = false;
+        // Part of current line is already forwarded to a streaming command
+        let mut streaming = false;
+        loop {
+            while let Some(pos) = received.iter().position(|c| *c == b'\n') {
+                let raw = received.drain(..=pos).collect::<Vec<_>>();
+                let line = String::from_utf8_lossy(&raw);
+                // Lossy decoding only allocates when it had to replace something
+                let invalid = matches!(line, Cow::Owned(_)) && strict_utf8.load(Ordering::Relaxed);
+                let line = line.trim();
+                trace!("Received: {}", line);
+                if std::mem::take(&mut streaming) {
+                    if let Some(sender) = Self::front_records(&pending) {
+                        Self::forward_records(&pending, &sender, line).await;
+                    }
+                    continue;
+                }
+                if line.is_empty() {
+                    continue;
+                }
+
+                if Notification::is_notify(line) {
+                    let event = if invalid {
+                        Err(QueryError::invalid_utf8())
+                    } else {
+                        Notification::from_line(line)
+                    };
+                    if let Ok(notification) = &event {
+                        subscribers.lock().unwrap().retain(|subscriber| {
+                            !matches!(
+                                subscriber.try_send(notification.clone()),
+                                Err(mpsc::error::TrySendError::Closed(_))
+                            )
+                        });
+                    }
+                    if events.try_send(event).is_err() {
+                        debug!("Event channel is full or closed, drop: {}", line);
+                    }
+                    continue;
+                }
+
+                let is_status = line.starts_with("error ");
+                if reply.is_empty() && !is_status && pending.lock().unwrap().is_empty() {
+                    debug!("Drop unsolicited line: {}", line);
+                    continue;
+                }
+
+                if let Some(sender) = Self::front_records(&pending) {
+                    Self::forward_records(&pending, &sender, line).await;
+                    // Only the reader pops, so front is still this stream
+                    if is_status {
+                        pending.lock().unwrap().pop_front();
+                    }
+                    continue;
+                }
+
+                reply.push_str(line);
+                reply.push('\n');
+                reply_invalid |= invalid;
+                if is_status {
+                    let content = std::mem::take(&mut reply);
+                    // Check and pop under one lock, a command may be queued meanwhile
+                    let popped = {
+                        let mut pending = pending.lock().unwrap();
+                        match pending.front() {
+                            Some(Pending::Reply(_, Some(marker)))
+                                if !Self::is_plausible(&content, marker) =>
+                            {
+                                None
+                            }
+                            _ => Some(pending.pop_front()),
+                        }
+                    };
+                    let Some(popped) = popped else {
+                        warn!(
+                            "Reply does not match pending command, resync: {:?}",
+                            content
+                        );
+                        reply_invalid = false;
+                        continue;
+                    };
+                    let content = if std::mem::take(&mut reply_invalid) {
+                        Err(QueryError::invalid_utf8())
+                    } else {
+                        Ok(content)
+                    };
+                    match popped {
+                        Some(Pending::Reply(sender, _)) => {
+                            if let Err(content) = sender.send(content) {
+                                warn!("Reply receiver is gone, discard: {:?}", content);
+                            }
+                        }
+                        // Stream was queued after the records check above, so this
+                        // status line is all of its reply
+                        Some(Pending::Records(sender)) => {
+                            Self::send_record(&sender, line).await;
+                        }
+                        None => warn!("Got reply without pending command: {:?}", content),
+                    }
+                }
+            }
+
+            // Forward finished records of a long line before rest of it arrives
+            if let Some(pos) = received.iter().rposition(|c| *c == b'|') {
+                if let Some(sender) = Self::front_records(&pending) {
+                    if streaming || Self::is_partial_records(&received) {
+                        let raw = received.drain(..=pos).collect::<Vec<_>>();
+                        Self::forward_records(
+                            &pending,
+                            &sender,
+                            String::from_utf8_lossy(&raw).trim(),
+                        )
+                        .await;
+                        streaming = true;
+                    }
+                }
+            }
+
+            let size = match reader.read(&mut buffer).await {
+                Ok(0) => {
+                    warn!("Connection closed by remote");
+                    break;
+                }
+                Ok(size) => size,
+                Err(e) => {
+                    error!("Got error while read data: {:?}", e);
+                    break;
+                }
+            };
+            counters.bytes_read(size);
+            received.extend_from_slice(&buffer[..size]);
+        }
+        // Drop all senders, so waiting commands and subscribers know connection is closed
+        pending.lock().unwrap().clear();
+        subscribers.lock().unwrap().clear();
+    }
+
+    // Commands are passed without terminator, it is only appended here
+    async fn write_data(&mut self, command: &str) -> QueryResult<()> {
+        debug_assert!(
+            !command.ends_with(['\n', '\r']),
+            "command should not contain terminator: {:?}",
+            command
+        );
+        trace!("Send: {}", Self::redact(command));
+        let payload = format!("{}{}", command, TERMINATOR);
+        self.writer.write(payload.as_bytes()).await.map(|size| {
+            self.counters.command_sent(size);
+            if size != payload.as_bytes().len() {
+                error!(
+                    "Error payload size mismatch! expect {} but {} found. payload: {:?}",
+                    payload.as_bytes().len(),
+                    size,
+                    payload
+                )
+            }
+        })?;
         Ok(())
     }
 
+    // Keep api key out of trace log
+    fn redact(command: &str) -> &str {
+        if command.starts_with("auth ") {
+            "auth apikey=<redacted>"
+        } else if command.starts_with("login ") {
+            "login <redacted>"
+        } else {
+            command
+        }
+    }
+
+    fn is_banner(line: &str) -> bool {
+        BANNER_PREFIXES
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+    }
+
     fn decode_status(content: String) -> QueryResult<String> {
-        /*debug_assert!(
-            !content.contains("Welcome to the TeamSpeak 3") && content.contains("error id="),
-            "Content => {:?}",
-            content
-        );*/
+        let mut lines = Vec::new();
+        let mut notifies = Vec::new();
 
         for line in content.lines() {
-            if line.trim().starts_with("error ") {
+            let line = line.trim();
+            if line.is_empty() || Self::is_banner(line) {
+                continue;
+            }
+            if line.starts_with("notify") {
+                notifies.push(line);
+                continue;
+            }
+            lines.push(line);
+            if line.starts_with("error ") {
                 let status = QueryStatus::try_from(line)?;
 
-                return status.into_result(content);
+                return status.into_result(lines.join("\n"));
             }
         }
+
+        if !notifies.is_empty() {
+            return Ok(notifies.join("\n"));
+        }
         error!("Should return status in reply => {}", content);
         Err(QueryError::status_not_found())
     }
@@ -85,56 +592,178 @@ impl SocketConn {
         let content = Self::decode_status(data)?;
 
         for line in content.lines() {
-            if !line.starts_with("error ") {
-                let mut v = Vec::new();
-                for element in line.split('|') {
-                    v.push(T::from_query(element)?);
-                }
-                return Ok(Some(v));
+            if !line.starts_with("error ") && !line.starts_with("notify") {
+                return Ok(Some(T::from_query_multi(line)?));
             }
         }
         Ok(None)
     }
 
-    async fn delay_read(&mut self) -> anyhow::Result<String> {
-        let mut s = String::new();
-        loop {
-            let r = self
-                .read_data()
-                .await?
-                .ok_or_else(|| anyhow!("READ NONE DATA"))?;
-            s.push_str(&r);
-            if s.lines().any(|line| line.trim().starts_with("error id=")) {
-                break;
+    fn reply_marker(payload: &str) -> Option<&'static str> {
+        let command = payload.split_whitespace().next().unwrap_or_default();
+        REPLY_MARKERS
+            .iter()
+            .find(|(name, _)| *name == command)
+            .map(|(_, marker)| *marker)
+    }
+
+    // Error replies and empty results have no record to check
+    fn is_plausible(content: &str, marker: &str) -> bool {
+        content
+            .lines()
+            .find(|line| !line.starts_with("error "))
+            .is_none_or(|line| line.starts_with(marker))
+    }
+
+    fn is_passthrough(payload: &str) -> bool {
+        let command = payload.split_whitespace().next().unwrap_or_default();
+        PASSTHROUGH_COMMANDS.contains(&command)
+    }
+
+    // Unregister of a dropped EventSubscription goes out before next command, its reply
+    // is only logged
+    async fn flush_unregister(&mut self) {
+        if !self.unregister_pending.swap(false, Ordering::Relaxed) || !self.is_connected() {
+            return;
+        }
+        self.events_registered = false;
+        let (sender, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .push_back(Pending::Reply(sender, None));
+        if let Err(e) = self.write_data("clientnotifyunregister").await {
+            self.pending.lock().unwrap().pop_back();
+            warn!("Unregister events of dropped subscription failed: {}", e);
+            return;
+        }
+        tokio::spawn(async move {
+            if let Ok(Err(e)) = receiver
+                .await
+                .map(|reply| reply.and_then(Self::decode_status))
+            {
+                warn!("Unregister events of dropped subscription failed: {}", e);
             }
+        });
+    }
+
+    // Write command and return receiver for its reply without waiting
+    async fn submit(
+        &mut self,
+        payload: &str,
+    ) -> QueryResult<oneshot::Receiver<QueryResult<String>>> {
+        let (sender, receiver) = oneshot::channel();
+        if self.dry_run && !Self::is_passthrough(payload) {
+            info!("Dry run, skip: {}", payload.trim_end());
+            sender.send(Ok(DRY_RUN_REPLY.to_string())).ok();
+            return Ok(receiver);
+        }
+        // Nobody would answer, do not wait for the reply timeout
+        if !self.is_connected() {
+            return Err(QueryError::connection_closed());
+        }
+        self.require_auth(payload)?;
+        self.flush_unregister().await;
+        self.pending
+            .lock()
+            .unwrap()
+            .push_back(Pending::Reply(sender, Self::reply_marker(payload)));
+        if let Err(e) = self.write_data(payload).await {
+            self.pending.lock().unwrap().pop_back();
+            return Err(e);
+        }
+        Ok(receiver)
+    }
+
+    async fn wait_reply(
+        receiver: oneshot::Receiver<QueryResult<String>>,
+        timeout: Duration,
+    ) -> QueryResult<String> {
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(content)) => content,
+            Ok(Err(_)) => Err(QueryError::connection_closed()),
+            Err(_) => Err(QueryError::timeout()),
+        }
+    }
+
+    async fn write_and_read(&mut self, payload: &str) -> QueryResult<String> {
+        let receiver = self.submit(payload).await?;
+        Self::wait_reply(receiver, self.reply_timeout).await
+    }
+
+    async fn write_and_stream<T: FromQueryString + Sized>(
+        &mut self,
+        payload: &str,
+    ) -> QueryResult<RecordStream<T>> {
+        let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_SIZE);
+        if !self.is_connected() {
+            return self.record(Err(QueryError::connection_closed()));
+        }
+        if let Err(e) = self.require_auth(payload) {
+            return self.record(Err(e));
         }
-        Ok(s)
+        self.flush_unregister().await;
+        self.pending
+            .lock()
+            .unwrap()
+            .push_back(Pending::Records(sender));
+        if let Err(e) = self.write_data(payload).await {
+            self.pending.lock().unwrap().pop_back();
+            return self.record(Err(e));
+        }
+        Ok(RecordStream {
+            receiver,
+            timeout: self.reply_timeout,
+            done: false,
+            _marker: PhantomData,
+        })
     }
 
-    async fn write_and_read(&mut self, payload: &str) -> anyhow::Result<String> {
-        self.write_data(payload).await?;
-        self.delay_read().await
+    fn record<T>(&self, ret: QueryResult<T>) -> QueryResult<T> {
+        if let Err(ref e) = ret {
+            self.counters.error(e.code());
+        }
+        ret
     }
 
     async fn basic_operation(&mut self, payload: &str) -> QueryResult<()> {
-        let data = self.write_and_read(payload).await?;
-        Self::decode_status(data).map(|_| ())
+        let ret = self
+            .write_and_read(payload)
+            .await
+            .and_then(|data| Self::decode_status(data).map(|_| ()));
+        self.record(ret)
     }
 
     async fn query_operation_non_error<T: FromQueryString + Sized>(
         &mut self,
         payload: &str,
     ) -> QueryResult<Vec<T>> {
-        let data = self.write_and_read(payload).await?;
-        match Self::decode_status_with_result(data) {
-            Ok(ret) => ret,
-            Err(e) => {
-                if e.code() != -6 {
-                    return Err(e);
-                }
-                Self::decode_status_with_result(self.write_and_read(payload).await?)?
-            }
+        let ret = self.query_operation_non_error_inner(payload).await;
+        self.record(ret)
+    }
+
+    async fn query_operation_non_error_inner<T: FromQueryString + Sized>(
+        &mut self,
+        payload: &str,
+    ) -> QueryResult<Vec<T>> {
+        // Skipped command has nothing to parse, a made up record would be taken for real
+        if self.dry_run && !Self::is_passthrough(payload) {
+            let command = payload.split_whitespace().next().unwrap_or_default();
+            return Err(QueryError::dry_run(command));
         }
+        // Reply could be garbled by a late line of previous command, send it once more.
+        // Only read-only commands, anything else would create or change things twice.
+        let attempts = if Self::is_passthrough(payload) { 2 } else { 1 };
+        let policy =
+            RetryPolicy::new(attempts, Duration::ZERO).retry_if(QueryError::is_parse_error);
+        retry(&policy, self, |conn| {
+            let payload = payload.to_owned();
+            Box::pin(async move {
+                let data = conn.write_and_read(&payload).await?;
+                Self::decode_status_with_result(data)
+            })
+        })
+        .await?
         .ok_or_else(|| QueryError::result_not_found(payload))
     }
 
@@ -143,8 +772,11 @@ impl SocketConn {
         &mut self,
         payload: &str,
     ) -> QueryResult<Option<Vec<T>>> {
-        let data = self.write_and_read(payload).await?;
-        Self::decode_status_with_result(data)
+        let ret = self
+            .write_and_read(payload)
+            .await
+            .and_then(Self::decode_status_with_result);
+        self.record(ret)
         //let status = status.ok_or_else(|| anyhow!("Can't find status line."))?;
     }
 
@@ -152,123 +784,3471 @@ impl SocketConn {
         s.replace('\\', "\\\\")
             .replace(' ', "\\s")
             .replace('/', "\\/")
+            .replace('|', "\\p")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+            .replace('\t', "\\t")
     }
 
-    pub async fn connect(server: &str, port: u16) -> anyhow::Result<Self> {
-        let conn = TcpStream::connect(format!("{}:{}", server, port))
-            .await
-            .map_err(|e| anyhow!("Got error while connect to {}:{} {:?}", server, port, e))?;
+    fn socket_address(server: &str, port: u16) -> String {
+        let host = server.trim_start_matches('[').trim_end_matches(']');
+        if host.parse::<Ipv6Addr>().is_ok() {
+            format!("[{}]:{}", host, port)
+        } else {
+            format!("{}:{}", host, port)
+        }
+    }
+
+    fn format_properties(properties: &[(&str, &str)]) -> String {
+        properties
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, Self::escape(value)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // CRC-32 (IEEE), which TeamSpeak uses as icon id
+    fn crc32(data: &[u8]) -> u32 {
+        !data.iter().fold(!0u32, |crc, byte| {
+            (0..8).fold(crc ^ *byte as u32, |crc, _| {
+                (crc >> 1) ^ (0xEDB88320 & (!(crc & 1)).wrapping_add(1))
+            })
+        })
+    }
+
+    /// Replace `{name}` placeholders, e.g. `{nickname}` or `{reason}`, with values from
+    /// `context`. Unknown placeholders are kept as is, escaping happens when sending.
+    pub fn render_template(template: &str, context: &[(&str, &str)]) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let value = rest.find('}').and_then(|end| {
+                context
+                    .iter()
+                    .find(|(key, _)| *key == &rest[1..end])
+                    .map(|(_, value)| (end, value))
+            });
+            match value {
+                Some((end, value)) => {
+                    out.push_str(value);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    out.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    // Same key repeated for each value, joined by `|`, e.g. `cid=1|cid=2`
+    fn format_list(key: &str, values: &[i64]) -> String {
+        values
+            .iter()
+            .map(|value| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+
+    async fn connect_stream(server: &str, port: u16) -> QueryResult<TcpStream> {
+        let address = Self::socket_address(server, port);
+        let mut last_error = None;
+        for addr in tokio::net::lookup_host(&address).await? {
+            match TcpStream::connect(addr).await {
+                Ok(conn) => return Ok(conn),
+                Err(e) => {
+                    warn!("Connect to {} failed, try next address: {:?}", addr, e);
+                    last_error.replace(e);
+                }
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("No address resolved from {}", address),
+                )
+            })
+            .into())
+    }
 
-        //let bufreader = BufReader::new(conn);
-        //conn.set_nonblocking(true).unwrap();
-        let mut self_ = Self { conn };
+    pub async fn connect(server: &str, port: u16) -> QueryResult<Self> {
+        let mut conn = Self::connect_stream(server, port).await?;
 
         tokio::time::sleep(Duration::from_millis(10)).await;
-        let content = self_
-            .read_data()
-            .await
-            .map_err(|e| anyhow!("Got error in connect while read content: {:?}", e))?;
+        let content = Self::read_banner(&mut conn).await?;
+
+        let (interface, state, received) = match content {
+            Some(content) => {
+                let (banner, received) = Self::split_banner(content);
+                let interface = Interface::from_banner(&banner);
+                let state = if banner.contains("Use the \"auth\" command")
+                    || interface == Interface::Server
+                {
+                    ConnState::Connected
+                } else {
+                    ConnState::Authenticated
+                };
+                (interface, state, received)
+            }
+            None => {
+                debug!("No banner received before timeout");
+                (Interface::Unknown, ConnState::Authenticated, Vec::new())
+            }
+        };
 
-        if content.is_none() {
-            warn!("Read none data.");
+        let (reader, writer) = conn.into_split();
+        let pending = PendingReplies::default();
+        let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_SIZE);
+        let subscribers = Subscribers::default();
+        let counters = Arc::new(Counters::default());
+        let strict_utf8 = Arc::new(AtomicBool::new(false));
+        let state = Arc::new(watch::channel(state).0);
+        let read_loop = Self::read_loop(
+            reader,
+            pending.clone(),
+            sender,
+            subscribers.clone(),
+            counters.clone(),
+            strict_utf8.clone(),
+            received,
+        );
+        let closed = state.clone();
+
+        Ok(Self {
+            writer,
+            pending,
+            events: Some(receiver),
+            subscribers,
+            reader: tokio::spawn(async move {
+                read_loop.await;
+                if *closed.borrow() != ConnState::Closed {
+                    closed.send_replace(ConnState::Closed);
+                }
+            }),
+            counters,
+            strict_utf8,
+            unregister_pending: Default::default(),
+            events_registered: false,
+            dry_run: false,
+            reply_timeout: DEFAULT_REPLY_TIMEOUT,
+            login_timeout: DEFAULT_LOGIN_TIMEOUT,
+            server: server.to_string(),
+            port,
+            interface,
+            state,
+            list_cache_ttl: None,
+            client_cache: None,
+            channel_cache: None,
+            next_transfer_id: 1,
+        })
+    }
+
+    /// Retry initial connection, e.g. the client is not up yet right after boot. Every
+    /// attempt tries all resolved addresses, the delay doubles after each failed attempt.
+    pub async fn connect_with_retry(
+        server: &str,
+        port: u16,
+        attempts: u32,
+        backoff: Duration,
+    ) -> QueryResult<Self> {
+        let policy = RetryPolicy::new(attempts, backoff).retry_if(QueryError::is_io_error);
+        retry(&policy, &mut (), |_| {
+            let server = server.to_owned();
+            Box::pin(async move { Self::connect(&server, port).await })
+        })
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    #[allow(dead_code)]
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    #[allow(dead_code)]
+    pub fn interface(&self) -> Interface {
+        self.interface
+    }
+
+    // Fail early instead of sending a command the interface rejects with an opaque error,
+    // unknown interface is given the benefit of the doubt
+    fn require_interface(&self, interface: Interface, command: &str) -> QueryResult<()> {
+        if self.interface == Interface::Unknown || self.interface == interface {
+            Ok(())
+        } else {
+            Err(QueryError::unsupported_interface(command))
         }
+    }
 
-        Ok(self_)
+    // Commands which may change something are logged instead of sent
+    #[allow(dead_code)]
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
     }
 
-    pub async fn login(&mut self, key: &str) -> QueryResult<()> {
-        let payload = format!("auth apikey={}\n\r", key);
-        self.basic_operation(payload.as_str()).await
+    /// Fail a reply (or event) containing invalid UTF-8 with `QueryError::INVALID_UTF8`
+    /// instead of replacing the bytes, which usually means a protocol desync or a wrong
+    /// port. Records of streamed commands are always decoded lossily.
+    #[allow(dead_code)]
+    pub fn set_strict_utf8(&self, strict: bool) {
+        self.strict_utf8.store(strict, Ordering::Relaxed);
     }
 
     #[allow(dead_code)]
-    pub async fn who_am_i(&mut self) -> QueryResult<WhoAmI> {
-        self.query_operation_non_error("whoami\n\r")
-            .await
-            .map(|mut v| v.remove(0))
+    pub fn set_reply_timeout(&mut self, timeout: Duration) {
+        self.reply_timeout = timeout;
     }
 
-    pub async fn query_clients(&mut self) -> QueryResult<Vec<Client>> {
-        self.query_operation_non_error("clientlist\n\r").await
+    // Only for `login`, which does not use the reply timeout
+    pub fn set_login_timeout(&mut self, timeout: Duration) {
+        self.login_timeout = timeout;
     }
 
+    /// Serve `query_clients`/`query_channels` from the last reply within `ttl`, for
+    /// callers polling frequently. Moves, kicks and bans by this connection invalidate
+    /// it, changes by others are only seen after `ttl`. Disabled by default.
     #[allow(dead_code)]
-    pub async fn logout(&mut self) -> QueryResult<()> {
-        self.basic_operation("quit\n\r").await
+    pub fn set_list_cache_ttl(&mut self, ttl: Option<Duration>) {
+        self.list_cache_ttl = ttl;
+        self.invalidate_list_cache();
+    }
+
+    fn invalidate_list_cache(&mut self) {
+        self.client_cache = None;
+        self.channel_cache = None;
+    }
+
+    fn cached<T: Clone>(cache: &CachedList<T>, ttl: Option<Duration>) -> Option<Vec<T>> {
+        match (cache, ttl) {
+            (Some((fetched, list)), Some(ttl)) if fetched.elapsed() < ttl => Some(list.clone()),
+            _ => None,
+        }
+    }
+
+    // Reader task stops once the server closed the socket
+    pub fn is_connected(&self) -> bool {
+        self.state() != ConnState::Closed && !self.reader.is_finished()
     }
 
     #[allow(dead_code)]
-    pub async fn set_current_channel_password(&mut self, password: &str) -> QueryResult<()> {
-        let me = self.who_am_i().await?;
-        self.set_channel_password(me.channel_id(), password).await
+    pub fn is_authenticated(&self) -> bool {
+        self.state() == ConnState::Authenticated && self.is_connected()
     }
 
-    pub async fn set_channel_password(&mut self, cid: i64, password: &str) -> QueryResult<()> {
-        self.basic_operation(&format!(
-            "channeledit cid={} channel_password={}\n\r",
-            cid,
+    pub fn state(&self) -> ConnState {
+        *self.state.borrow()
+    }
+
+    /// Receiver sees every state change from now on, e.g. to pause work once the
+    /// connection is closed
+    pub fn subscribe_state(&self) -> watch::Receiver<ConnState> {
+        self.state.subscribe()
+    }
+
+    // Receivers are only woken by an actual change. Closed is final, reader task may
+    // have set it while a reply was still being handled.
+    fn set_state(&self, state: ConnState) {
+        let previous = self.state();
+        if previous != state && previous != ConnState::Closed {
+            debug!("Connection state {:?} -> {:?}", previous, state);
+            self.state.send_replace(state);
+        }
+    }
+
+    // Server would only answer not logged in, save the round trip
+    fn require_auth(&self, payload: &str) -> QueryResult<()> {
+        let command = payload.split_whitespace().next().unwrap_or_default();
+        if self.state() == ConnState::Connected && !AUTH_FREE_COMMANDS.contains(&command) {
+            return Err(QueryError::not_authenticated(command));
+        }
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn stats(&self) -> ConnStats {
+        self.counters.snapshot()
+    }
+
+    #[allow(dead_code)]
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.writer.peer_addr()
+    }
+
+    #[allow(dead_code)]
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.writer.local_addr()
+    }
+
+    pub async fn login(&mut self, key: &str) -> QueryResult<()> {
+        self.require_interface(Interface::Client, "auth apikey")?;
+        self.authenticate(&format!("auth apikey={}", key), QueryError::auth_failed)
+            .await
+    }
+
+    /// Log in to server query with a query account, e.g. serveradmin
+    #[allow(dead_code)]
+    pub async fn server_login(&mut self, name: &str, password: &str) -> QueryResult<()> {
+        self.require_interface(Interface::Server, "login")?;
+        let payload = format!(
+            "login client_login_name={} client_login_password={}",
+            Self::escape(name),
             Self::escape(password)
-        ))
-        .await
+        );
+        self.authenticate(&payload, QueryError::login_failed).await
+    }
+
+    // Rejected credentials become `rejected`, connection stays usable to try again
+    async fn authenticate(
+        &mut self,
+        payload: &str,
+        rejected: fn() -> QueryError,
+    ) -> QueryResult<()> {
+        let ret = match self.submit(payload).await {
+            Ok(receiver) => Self::wait_reply(receiver, self.login_timeout)
+                .await
+                .and_then(|data| Self::decode_status(data).map(|_| ())),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| match e.code() {
+            QueryError::INVALID_PARAMETER
+            | QueryError::CURRENTLY_NOT_POSSIBLE
+            | QueryError::INVALID_LOGIN => rejected(),
+            _ => e,
+        });
+        self.record(ret)?;
+        self.set_state(ConnState::Authenticated);
+        Ok(())
     }
 
     #[allow(dead_code)]
-    pub async fn server_connect_info(&mut self) -> QueryResult<ConnectInfo> {
-        self.query_operation_non_error("serverconnectinfo\n\r")
+    pub async fn who_am_i(&mut self) -> QueryResult<WhoAmI> {
+        self.query_operation_non_error("whoami")
             .await
             .map(|mut v| v.remove(0))
     }
 
     #[allow(dead_code)]
-    pub async fn switch_channel(&mut self, channel_id: i64) -> QueryResult<()> {
-        let me = self.who_am_i().await?;
-        self.basic_operation(&format!(
-            "clientmove cid={} clid={}\n\r",
-            channel_id,
-            me.client_id(),
-        ))
-        .await
+    pub async fn ping(&mut self) -> QueryResult<Duration> {
+        let start = Instant::now();
+        self.who_am_i().await?;
+        Ok(start.elapsed())
     }
 
-    pub async fn query_database_id(&mut self) -> QueryResult<i64> {
-        let my = self.who_am_i().await?;
-        let mut database_id = 0;
-        for client in self
-            .query_clients()
-            .await
-            .map_err(|e| anyhow!("Got error while query clients: {:?}", e))?
-        {
-            if client.client_id() == my.client_id() {
-                database_id = client.client_database_id();
-            }
+    pub async fn query_clients(&mut self) -> QueryResult<Vec<Client>> {
+        if let Some(clients) = Self::cached(&self.client_cache, self.list_cache_ttl) {
+            return Ok(clients);
         }
-        if database_id == 0 {
-            return Err(QueryError::database_id_error());
+        self.refresh_clients().await
+    }
+
+    // Bypass list cache
+    pub async fn refresh_clients(&mut self) -> QueryResult<Vec<Client>> {
+        let clients: Vec<Client> = self.query_operation_non_error("clientlist").await?;
+        if self.list_cache_ttl.is_some() {
+            self.client_cache = Some((Instant::now(), clients.clone()));
         }
-        Ok(database_id)
+        Ok(clients)
     }
 
-    pub async fn update_client_description(&mut self, edit_var: ClientEdit) -> QueryResult<()> {
-        self.basic_operation(&format!(
-            "clientdbedit cldbid={} client_description={}\n\r",
-            edit_var.client_database_id(),
-            Self::escape(edit_var.description())
-        ))
-        .await
+    // Humans only, query clients (bots, this connection) are left out
+    #[allow(dead_code)]
+    pub async fn query_real_clients(&mut self) -> QueryResult<Vec<Client>> {
+        Ok(self
+            .query_clients()
+            .await?
+            .into_iter()
+            .filter(|client| client.client_kind() == ClientType::Regular)
+            .collect())
     }
 
-    pub async fn query_client_description(
+    #[allow(dead_code)]
+    pub async fn query_channels(&mut self) -> QueryResult<Vec<Channel>> {
+        if let Some(channels) = Self::cached(&self.channel_cache, self.list_cache_ttl) {
+            return Ok(channels);
+        }
+        self.refresh_channels().await
+    }
+
+    // Bypass list cache
+    #[allow(dead_code)]
+    pub async fn refresh_channels(&mut self) -> QueryResult<Vec<Channel>> {
+        let channels: Vec<Channel> = self.query_operation_non_error("channellist").await?;
+        if self.list_cache_ttl.is_some() {
+            self.channel_cache = Some((Instant::now(), channels.clone()));
+        }
+        Ok(channels)
+    }
+
+    // clientlist has no channel filter, so all clients are fetched
+    pub async fn clients_in_channel(&mut self, cid: i64) -> QueryResult<Vec<Client>> {
+        Ok(self
+            .query_clients()
+            .await?
+            .into_iter()
+            .filter(|client| client.channel_id() == cid)
+            .collect())
+    }
+
+    #[allow(dead_code)]
+    pub async fn query_clients_with_times(&mut self) -> QueryResult<Vec<Client>> {
+        self.query_operation_non_error("clientlist -times").await
+    }
+
+    // Talking state, see `Client::is_talking`
+    #[allow(dead_code)]
+    pub async fn query_clients_with_voice(&mut self) -> QueryResult<Vec<Client>> {
+        self.query_operation_non_error("clientlist -voice").await
+    }
+
+    #[allow(dead_code)]
+    pub async fn query_clients_with_uid(&mut self) -> QueryResult<Vec<Client>> {
+        self.query_operation_non_error("clientlist -uid").await
+    }
+
+    // `time` None means permanent, otherwise rounded down to seconds
+    async fn ban_add(
+        &mut self,
+        target: &str,
+        time: Option<Duration>,
+        reason: Option<&str>,
+    ) -> QueryResult<i64> {
+        self.invalidate_list_cache();
+        let mut payload = format!("banadd {}", target);
+        if let Some(time) = time {
+            payload.push_str(&format!(" time={}", time.as_secs()));
+        }
+        if let Some(reason) = reason {
+            payload.push_str(&format!(" banreason={}", Self::escape(reason)));
+        }
+        self.query_operation_non_error::<BanAdd>(&payload)
+            .await
+            .map(|mut v| v.remove(0).ban_id())
+    }
+
+    /// Ban an ip address (regular expression allowed by server), return the ban id
+    #[allow(dead_code)]
+    pub async fn ban_add_ip(
+        &mut self,
+        ip: &str,
+        time: Option<Duration>,
+        reason: Option<&str>,
+    ) -> QueryResult<i64> {
+        self.ban_add(&format!("ip={}", Self::escape(ip)), time, reason)
+            .await
+    }
+
+    /// Ban an identity which does not need to be online, return the ban id
+    #[allow(dead_code)]
+    pub async fn ban_add_uid(
+        &mut self,
+        uid: &str,
+        time: Option<Duration>,
+        reason: Option<&str>,
+    ) -> QueryResult<i64> {
+        self.ban_add(&format!("uid={}", Self::escape(uid)), time, reason)
+            .await
+    }
+
+    // `{duration}` is filled from `time` unless given in `context`
+    fn render_ban_reason(
+        time: Option<Duration>,
+        template: &str,
+        context: &[(&str, &str)],
+    ) -> String {
+        let duration = match time {
+            Some(time) => format!("{} seconds", time.as_secs()),
+            None => "permanent".to_string(),
+        };
+        let mut context = context.to_vec();
+        if !context.iter().any(|(key, _)| *key == "duration") {
+            context.push(("duration", &duration));
+        }
+        Self::render_template(template, &context)
+    }
+
+    /// `ban_add_ip` with reason rendered by `render_template`, `{duration}` is filled
+    /// from `time` unless given in `context`
+    #[allow(dead_code)]
+    pub async fn ban_add_ip_template(
+        &mut self,
+        ip: &str,
+        time: Option<Duration>,
+        template: &str,
+        context: &[(&str, &str)],
+    ) -> QueryResult<i64> {
+        let reason = Self::render_ban_reason(time, template, context);
+        self.ban_add_ip(ip, time, Some(&reason)).await
+    }
+
+    /// `ban_add_uid` with reason rendered by `render_template`, `{duration}` is filled
+    /// from `time` unless given in `context`
+    #[allow(dead_code)]
+    pub async fn ban_add_uid_template(
+        &mut self,
+        uid: &str,
+        time: Option<Duration>,
+        template: &str,
+        context: &[(&str, &str)],
+    ) -> QueryResult<i64> {
+        let reason = Self::render_ban_reason(time, template, context);
+        self.ban_add_uid(uid, time, Some(&reason)).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn ban_del(&mut self, ban_id: i64) -> QueryResult<()> {
+        self.basic_operation(&format!("bandel banid={}", ban_id))
+            .await
+    }
+
+    /// Remove every ban of the server, `confirm` must be true so a stray call can not
+    /// unban everyone by accident.
+    #[allow(dead_code)]
+    pub async fn ban_del_all(&mut self, confirm: bool) -> QueryResult<()> {
+        if !confirm {
+            return Err(QueryError::invalid_argument(
+                "bandelall removes all bans, pass confirm = true",
+            ));
+        }
+        self.basic_operation("bandelall").await
+    }
+
+    /// `client_ip` stays empty if the api key is not allowed to view ip addresses
+    #[allow(dead_code)]
+    pub async fn query_clients_with_ip(&mut self) -> QueryResult<Vec<Client>> {
+        self.query_operation_non_error("clientlist -ip").await
+    }
+
+    /// Wait until client with `uid` is online and return its clid. Listens on a
+    /// subscriber of its own, so the event stream and whoever took it see every event
+    /// as usual. Safe to cancel, e.g. in `tokio::select!` against a shutdown signal.
+    #[allow(dead_code)]
+    pub async fn wait_for_client(&mut self, uid: &str, timeout: Duration) -> QueryResult<i64> {
+        let deadline = Instant::now() + timeout;
+        let mut events = self.subscribe();
+        // Register before listing, so a client connecting in between is not missed.
+        // Registration of someone else is left as it is, unless it is about to be
+        // unregistered by a dropped subscription.
+        let subscription =
+            if self.events_registered && !self.unregister_pending.load(Ordering::Relaxed) {
+                None
+            } else {
+                Some(self.register_events().await?)
+            };
+        let ret = self.wait_for_enter(&mut events, uid, deadline).await;
+        if let Some(subscription) = subscription {
+            if self.is_connected() {
+                if let Err(e) = subscription.cancel(self).await {
+                    warn!("Unregister events after wait for client failed: {}", e);
+                }
+            }
+        }
+        ret
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<Notification> {
+        let (sender, receiver) = mpsc::channel(EVENT_CHANNEL_SIZE);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    async fn wait_for_enter(
+        &mut self,
+        events: &mut mpsc::Receiver<Notification>,
+        uid: &str,
+        deadline: Instant,
+    ) -> QueryResult<i64> {
+        if let Some(client_id) = self.find_online_client(uid).await? {
+            return Ok(client_id);
+        }
+        loop {
+            match tokio::time::timeout_at(deadline, events.recv()).await {
+                Err(_) => return Err(QueryError::timeout()),
+                Ok(None) => return Err(QueryError::connection_closed()),
+                Ok(Some(Notification::ClientEnterView(view)))
+                    if view.client_unique_identifier() == uid =>
+                {
+                    return Ok(view.client_id())
+                }
+                Ok(Some(_)) => {}
+            }
+        }
+    }
+
+    async fn find_online_client(&mut self, uid: &str) -> QueryResult<Option<i64>> {
+        Ok(self
+            .query_clients_with_uid()
+            .await?
+            .into_iter()
+            .find(|client| client.client_unique_identifier() == uid)
+            .map(|client| client.client_id()))
+    }
+
+    // Parse clients while reply still arriving, instead of buffering whole list
+    #[allow(dead_code)]
+    pub async fn query_clients_stream(&mut self) -> QueryResult<RecordStream<Client>> {
+        self.write_and_stream("clientlist").await
+    }
+
+    pub async fn register_events(&mut self) -> QueryResult<EventSubscription> {
+        self.unregister_pending.store(false, Ordering::Relaxed);
+        self.basic_operation("clientnotifyregister schandlerid=0 event=any")
+            .await?;
+        self.events_registered = true;
+        Ok(EventSubscription {
+            unregister: Some(self.unregister_pending.clone()),
+        })
+    }
+
+    #[allow(dead_code)]
+    pub async fn unregister_events(&mut self) -> QueryResult<()> {
+        self.basic_operation("clientnotifyunregister").await?;
+        self.events_registered = false;
+        Ok(())
+    }
+
+    // Notifies are only delivered after register_events, receiver can be taken once
+    #[allow(dead_code)]
+    pub fn take_events(&mut self) -> Option<mpsc::Receiver<QueryResult<Notification>>> {
+        self.events.take()
+    }
+
+    #[allow(dead_code)]
+    pub async fn logout(&mut self) -> QueryResult<()> {
+        self.basic_operation("quit").await?;
+        self.set_state(ConnState::Closed);
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub async fn set_current_channel_password(&mut self, password: &str) -> QueryResult<()> {
+        let me = self.who_am_i().await?;
+        self.set_channel_password(me.channel_id(), password).await
+    }
+
+    pub async fn set_channel_password(&mut self, cid: i64, password: &str) -> QueryResult<()> {
+        self.channel_edit(cid, &[("channel_password", password)])
+            .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_edit(&mut self, cid: i64, properties: &[(&str, &str)]) -> QueryResult<()> {
+        if properties.is_empty() {
+            return Err(QueryError::invalid_argument(
+                "properties should not be empty",
+            ));
+        }
+        self.basic_operation(&format!(
+            "channeledit cid={} {}",
+            cid,
+            Self::format_properties(properties)
+        ))
+        .await
+    }
+
+    fn validate_channel_name(name: &str) -> QueryResult<()> {
+        if name.trim().is_empty() {
+            return Err(QueryError::invalid_argument(
+                "channel name should not be empty",
+            ));
+        }
+        if name.chars().count() > MAX_CHANNEL_NAME_LENGTH {
+            return Err(QueryError::invalid_argument(&format!(
+                "channel name should not be longer than {} characters",
+                MAX_CHANNEL_NAME_LENGTH
+            )));
+        }
+        if name.chars().any(char::is_control) {
+            return Err(QueryError::invalid_argument(
+                "channel name should not contain control characters",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Name is checked against server limits (non-empty, at most 40 characters,
+    /// no control characters) before sending.
+    #[allow(dead_code)]
+    pub async fn rename_channel(&mut self, cid: i64, name: &str) -> QueryResult<()> {
+        Self::validate_channel_name(name)?;
+        self.channel_edit(cid, &[("channel_name", name)]).await
+    }
+
+    /// `order` is the cid of the channel this one should be placed after
+    /// (not a position index), use 0 to move it to the top.
+    #[allow(dead_code)]
+    pub async fn set_channel_order(&mut self, cid: i64, order: i64) -> QueryResult<()> {
+        self.channel_edit(cid, &[("channel_order", &order.to_string())])
+            .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn find_channels(&mut self, pattern: &str) -> QueryResult<Vec<ChannelFind>> {
+        match self
+            .query_operation_non_error(&format!("channelfind pattern={}", Self::escape(pattern)))
+            .await
+        {
+            // Server reply invalid channel id if nothing matched
+            Err(e) if e.code() == 768 => Ok(vec![]),
+            ret => ret,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn client_add_perm(
+        &mut self,
+        cldbid: i64,
+        permsid: &str,
+        value: i64,
+        skip: bool,
+        negate: bool,
+    ) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "clientaddperm cldbid={} permsid={} permvalue={} permskip={} permnegated={}",
+            cldbid,
+            Self::escape(permsid),
+            value,
+            skip as u8,
+            negate as u8
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn client_del_perm(&mut self, cldbid: i64, permsid: &str) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "clientdelperm cldbid={} permsid={}",
+            cldbid,
+            Self::escape(permsid)
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn get_dbid_from_uid(&mut self, uid: &str) -> QueryResult<i64> {
+        self.query_operation_non_error::<ClientDbFind>(&format!(
+            "clientgetdbidfromuid cluid={}",
+            Self::escape(uid)
+        ))
+        .await
+        .map(|v| v[0].client_database_id())
+    }
+
+    // Online client only, no record is returned on ClientQuery, where the uid is
+    // read by clientvariable instead
+    #[allow(dead_code)]
+    pub async fn get_uid_from_clid(&mut self, client_id: i64) -> QueryResult<String> {
+        let reply = self
+            .query_operation::<ClientUid>(&format!("clientgetuidfromclid clid={}", client_id))
+            .await?;
+        if let Some(mut uids) = reply {
+            return Ok(uids.remove(0).client_unique_identifier().to_string());
+        }
+        self.client_variables(client_id, &["client_unique_identifier"])
+            .await?
+            .remove("client_unique_identifier")
+            .ok_or_else(|| QueryError::variable_not_found(client_id))
+    }
+
+    #[allow(dead_code)]
+    pub async fn client_db_find(
+        &mut self,
+        pattern: &str,
+        by_uid: bool,
+    ) -> QueryResult<Vec<ClientDbFind>> {
+        let payload = format!(
+            "clientdbfind pattern={}{}",
+            Self::escape(pattern),
+            if by_uid { " -uid" } else { "" }
+        );
+        match self.query_operation_non_error(&payload).await {
+            // Server reply database empty result set if nothing matched
+            Err(e) if e.code() == 1281 => Ok(vec![]),
+            ret => ret,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_info(&mut self, cid: i64) -> QueryResult<ChannelInfo> {
+        self.query_operation_non_error(&format!("channelinfo cid={}", cid))
+            .await
+            .map(|mut v| v.remove(0))
+    }
+
+    #[allow(dead_code)]
+    pub async fn connection_info(&mut self, client_id: i64) -> QueryResult<ConnectionInfo> {
+        self.query_operation_non_error(&format!("clientinfo clid={}", client_id))
+            .await
+            .map(|mut v| v.remove(0))
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_perm_list(&mut self, cid: i64) -> QueryResult<Vec<ChannelPerm>> {
+        match self
+            .query_operation_non_error(&format!("channelpermlist cid={} -permsid", cid))
+            .await
+        {
+            Err(e) if e.code() == 1281 => Ok(vec![]),
+            ret => ret,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_add_perm(
+        &mut self,
+        cid: i64,
+        permsid: &str,
+        value: i64,
+    ) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "channeladdperm cid={} permsid={} permvalue={}",
+            cid,
+            Self::escape(permsid),
+            value
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_del_perm(&mut self, cid: i64, permsid: &str) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "channeldelperm cid={} permsid={}",
+            cid,
+            Self::escape(permsid)
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_request_connection_info(&mut self) -> QueryResult<ServerConnectionInfo> {
+        self.require_interface(Interface::Server, "serverrequestconnectioninfo")?;
+        self.query_operation_non_error("serverrequestconnectioninfo")
+            .await
+            .map(|mut v| v.remove(0))
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_client_perm_list(
+        &mut self,
+        cid: i64,
+        cldbid: i64,
+    ) -> QueryResult<Vec<ChannelClientPerm>> {
+        match self
+            .query_operation_non_error(&format!(
+                "channelclientpermlist cid={} cldbid={} -permsid",
+                cid, cldbid
+            ))
+            .await
+        {
+            // Server reply database empty result set if no permission assigned
+            Err(e) if e.code() == 1281 => Ok(vec![]),
+            ret => ret,
+        }
+    }
+
+    // Only groups of `kind` if given, e.g. `GroupType::Regular` for assignable ones
+    #[allow(dead_code)]
+    pub async fn channel_group_list(
+        &mut self,
+        kind: Option<GroupType>,
+    ) -> QueryResult<Vec<ChannelGroup>> {
+        let mut groups: Vec<ChannelGroup> =
+            self.query_operation_non_error("channelgrouplist").await?;
+        if let Some(kind) = kind {
+            groups.retain(|group| group.kind() == kind);
+        }
+        Ok(groups)
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_group_list(
+        &mut self,
+        kind: Option<GroupType>,
+    ) -> QueryResult<Vec<ServerGroup>> {
+        let mut groups: Vec<ServerGroup> =
+            self.query_operation_non_error("servergrouplist").await?;
+        if let Some(kind) = kind {
+            groups.retain(|group| group.kind() == kind);
+        }
+        Ok(groups)
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_group_rename(&mut self, cgid: i64, name: &str) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "channelgrouprename cgid={} name={}",
+            cgid,
+            Self::escape(name)
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn set_client_channel_group(
+        &mut self,
+        cgid: i64,
+        cid: i64,
+        cldbid: i64,
+    ) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "setclientchannelgroup cgid={} cid={} cldbid={}",
+            cgid, cid, cldbid
+        ))
+        .await
+    }
+
+    /// Redeem a privilege key for the current connection, an unknown or already used
+    /// key is rejected by the server with its own error
+    #[allow(dead_code)]
+    pub async fn token_use(&mut self, token: &str) -> QueryResult<()> {
+        if token.is_empty() {
+            return Err(QueryError::invalid_argument("token should not be empty"));
+        }
+        self.basic_operation(&format!("privilegekeyuse token={}", Self::escape(token)))
+            .await
+    }
+
+    /// Reset all permissions to default, return the new admin privilege key
+    #[allow(dead_code)]
+    pub async fn perm_reset(&mut self) -> QueryResult<String> {
+        self.query_operation_non_error::<PermReset>("permreset")
+            .await
+            .map(|mut v| v.remove(0).token().to_string())
+    }
+
+    /// `type_` is 0 template, 1 regular, 2 query
+    #[allow(dead_code)]
+    pub async fn server_group_add(&mut self, name: &str, type_: i64) -> QueryResult<i64> {
+        self.query_operation_non_error::<ServerGroupId>(&format!(
+            "servergroupadd name={} type={}",
+            Self::escape(name),
+            type_
+        ))
+        .await
+        .map(|mut v| v.remove(0).sgid())
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_group_rename(&mut self, sgid: i64, name: &str) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "servergrouprename sgid={} name={}",
+            sgid,
+            Self::escape(name)
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_group_add_perm(
+        &mut self,
+        sgid: i64,
+        permsid: &str,
+        value: i64,
+        skip: bool,
+        negate: bool,
+    ) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "servergroupaddperm sgid={} permsid={} permvalue={} permskip={} permnegated={}",
+            sgid,
+            Self::escape(permsid),
+            value,
+            skip as u8,
+            negate as u8
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_group_del_perm(&mut self, sgid: i64, permsid: &str) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "servergroupdelperm sgid={} permsid={}",
+            sgid,
+            Self::escape(permsid)
+        ))
+        .await
+    }
+
+    /// Without `force` the server refuses to delete a group which still has members
+    #[allow(dead_code)]
+    pub async fn server_group_del(&mut self, sgid: i64, force: bool) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "servergroupdel sgid={} force={}",
+            sgid, force as u8
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_group_add(&mut self, name: &str, type_: i64) -> QueryResult<i64> {
+        self.query_operation_non_error::<ChannelGroupId>(&format!(
+            "channelgroupadd name={} type={}",
+            Self::escape(name),
+            type_
+        ))
+        .await
+        .map(|mut v| v.remove(0).cgid())
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_group_del(&mut self, cgid: i64, force: bool) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "channelgroupdel cgid={} force={}",
+            cgid, force as u8
+        ))
+        .await
+    }
+
+    /// Copy permissions of `source_sgid` into `target_sgid`, or into a new group named
+    /// `name` if target is 0, return the group id. `type_` is 0 template, 1 regular, 2 query.
+    #[allow(dead_code)]
+    pub async fn server_group_copy(
+        &mut self,
+        source_sgid: i64,
+        target_sgid: i64,
+        name: &str,
+        type_: i64,
+    ) -> QueryResult<i64> {
+        match self
+            .query_operation_non_error::<ServerGroupId>(&format!(
+                "servergroupcopy ssgid={} tsgid={} name={} type={}",
+                source_sgid,
+                target_sgid,
+                Self::escape(name),
+                type_
+            ))
+            .await
+        {
+            Ok(mut v) => Ok(v.remove(0).sgid()),
+            // Nothing is returned when copying into an existing group
+            Err(e) if e.code() == QueryError::RESULT_NOT_FOUND && target_sgid != 0 => {
+                Ok(target_sgid)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Channel group version of `server_group_copy`
+    #[allow(dead_code)]
+    pub async fn channel_group_copy(
+        &mut self,
+        source_cgid: i64,
+        target_cgid: i64,
+        name: &str,
+        type_: i64,
+    ) -> QueryResult<i64> {
+        match self
+            .query_operation_non_error::<ChannelGroupId>(&format!(
+                "channelgroupcopy scgid={} tcgid={} name={} type={}",
+                source_cgid,
+                target_cgid,
+                Self::escape(name),
+                type_
+            ))
+            .await
+        {
+            Ok(mut v) => Ok(v.remove(0).cgid()),
+            Err(e) if e.code() == QueryError::RESULT_NOT_FOUND && target_cgid != 0 => {
+                Ok(target_cgid)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_group_client_list(
+        &mut self,
+        sgid: i64,
+    ) -> QueryResult<Vec<ServerGroupClient>> {
+        match self
+            .query_operation_non_error(&format!("servergroupclientlist sgid={} -names", sgid))
+            .await
+        {
+            // Database empty result set
+            Err(e) if e.code() == 1281 => Ok(vec![]),
+            ret => ret,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_group_client_list(
+        &mut self,
+        cid: Option<i64>,
+        cldbid: Option<i64>,
+        cgid: Option<i64>,
+    ) -> QueryResult<Vec<ChannelGroupClient>> {
+        let mut payload = String::from("channelgroupclientlist");
+        for (key, value) in [("cid", cid), ("cldbid", cldbid), ("cgid", cgid)] {
+            if let Some(value) = value {
+                payload.push_str(&format!(" {}={}", key, value));
+            }
+        }
+        match self.query_operation_non_error(&payload).await {
+            // Database empty result set
+            Err(e) if e.code() == 1281 => Ok(vec![]),
+            ret => ret,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_connect_info(&mut self) -> QueryResult<ConnectInfo> {
+        self.require_interface(Interface::Client, "serverconnectinfo")?;
+        self.query_operation_non_error("serverconnectinfo")
+            .await
+            .map(|mut v| v.remove(0))
+    }
+
+    // Link to the channel this connection is currently in
+    #[allow(dead_code)]
+    pub async fn rejoin_url(&mut self, password: Option<&str>) -> QueryResult<String> {
+        let info = self.server_connect_info().await?;
+        let me = self.who_am_i().await?;
+        Ok(info.join_url(me.channel_id(), password))
+    }
+
+    #[allow(dead_code)]
+    pub async fn switch_channel(&mut self, channel_id: i64, verify: bool) -> QueryResult<()> {
+        let me = self.who_am_i().await?;
+        self.move_client(me.client_id(), channel_id, None, verify)
+            .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn create_channel(
+        &mut self,
+        name: &str,
+        properties: &[(&str, &str)],
+    ) -> QueryResult<i64> {
+        self.invalidate_list_cache();
+        let mut payload = format!("channelcreate channel_name={}", Self::escape(name));
+        if !properties.is_empty() {
+            payload.push(' ');
+            payload.push_str(&Self::format_properties(properties));
+        }
+        self.query_operation_non_error::<CreateChannel>(&payload)
+            .await
+            .map(|mut v| v.remove(0).cid())
+    }
+
+    /// Create channel and move ourselves into it. The channel exists even if the move
+    /// fails, so its cid is returned together with the move error in that case.
+    #[allow(dead_code)]
+    pub async fn create_channel_and_join(
+        &mut self,
+        name: &str,
+        properties: &[(&str, &str)],
+    ) -> QueryResult<(i64, Option<QueryError>)> {
+        let cid = self.create_channel(name, properties).await?;
+        match self.switch_channel(cid, false).await {
+            // Client connection is moved into the channel it created by server
+            Err(e) if e.code() != ALREADY_MEMBER_OF_CHANNEL => {
+                warn!("Channel {} created, but join failed: {}", cid, e);
+                Ok((cid, Some(e)))
+            }
+            _ => Ok((cid, None)),
+        }
+    }
+
+    async fn find_channel_exact(&mut self, name: &str) -> QueryResult<Option<i64>> {
+        Ok(self
+            .find_channels(name)
+            .await?
+            .iter()
+            .find(|channel| channel.channel_name() == name)
+            .map(|channel| channel.cid()))
+    }
+
+    /// Cid of the channel named exactly `name`, created with `properties` if absent.
+    #[allow(dead_code)]
+    pub async fn ensure_channel(
+        &mut self,
+        name: &str,
+        properties: &[(&str, &str)],
+    ) -> QueryResult<i64> {
+        if let Some(cid) = self.find_channel_exact(name).await? {
+            return Ok(cid);
+        }
+        match self.create_channel(name, properties).await {
+            // Someone else created it between find and create
+            Err(e) if e.code() == CHANNEL_NAME_IN_USE => {
+                self.find_channel_exact(name).await?.ok_or(e)
+            }
+            ret => ret,
+        }
+    }
+
+    /// Move ourselves into the channel named `name`. A channel with exactly this
+    /// name wins over partial matches, otherwise the match must be unique.
+    #[allow(dead_code)]
+    pub async fn switch_to_channel_named(
+        &mut self,
+        name: &str,
+        password: Option<&str>,
+    ) -> QueryResult<()> {
+        let channels = self.find_channels(name).await?;
+        let exact = channels
+            .iter()
+            .filter(|channel| channel.channel_name() == name)
+            .map(|channel| channel.cid())
+            .collect::<Vec<_>>();
+        let cid = match (exact.as_slice(), channels.as_slice()) {
+            ([cid], _) => *cid,
+            ([], [channel]) => channel.cid(),
+            ([], []) => return Err(QueryError::channel_not_found(name)),
+            ([], _) => return Err(QueryError::ambiguous_channel(name, channels.len())),
+            (_, _) => return Err(QueryError::ambiguous_channel(name, exact.len())),
+        };
+        let me = self.who_am_i().await?;
+        self.move_client(me.client_id(), cid, password, false).await
+    }
+
+    /// With `verify`, the client list is queried again after the move, it fails with
+    /// `QueryError::MOVE_NOT_APPLIED` if the server accepted the move without applying it.
+    #[allow(dead_code)]
+    pub async fn move_client(
+        &mut self,
+        client_id: i64,
+        channel_id: i64,
+        password: Option<&str>,
+        verify: bool,
+    ) -> QueryResult<()> {
+        self.invalidate_list_cache();
+        let mut payload = format!("clientmove cid={} clid={}", channel_id, client_id);
+        if let Some(password) = password {
+            payload.push_str(&format!(" cpw={}", Self::escape(password)));
+        }
+        self.basic_operation(&payload).await?;
+        if !verify || self.dry_run {
+            return Ok(());
+        }
+        let moved = self
+            .refresh_clients()
+            .await?
+            .iter()
+            .any(|client| client.client_id() == client_id && client.channel_id() == channel_id);
+        if moved {
+            Ok(())
+        } else {
+            Err(QueryError::move_not_applied(client_id, channel_id))
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn move_clients_individually(
+        &mut self,
+        client_ids: &[i64],
+        channel_id: i64,
+    ) -> Vec<(i64, QueryResult<()>)> {
+        let mut ret = Vec::new();
+        for client_id in client_ids {
+            ret.push((
+                *client_id,
+                self.move_client(*client_id, channel_id, None, false).await,
+            ));
+        }
+        ret
+    }
+
+    // reasonid=5 kick from server
+    #[allow(dead_code)]
+    pub async fn kick_client(&mut self, client_id: i64, reason: Option<&str>) -> QueryResult<()> {
+        self.invalidate_list_cache();
+        let mut payload = format!("clientkick clid={} reasonid=5", client_id);
+        if let Some(reason) = reason {
+            payload.push_str(&format!(" reasonmsg={}", Self::escape(reason)));
+        }
+        self.basic_operation(&payload).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn kick_client_template(
+        &mut self,
+        client_id: i64,
+        template: &str,
+        context: &[(&str, &str)],
+    ) -> QueryResult<()> {
+        let reason = Self::render_template(template, context);
+        self.kick_client(client_id, Some(&reason)).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn kick_clients(
+        &mut self,
+        client_ids: &[i64],
+        reason: Option<&str>,
+    ) -> Vec<(i64, QueryResult<()>)> {
+        let mut ret = Vec::new();
+        for client_id in client_ids {
+            ret.push((*client_id, self.kick_client(*client_id, reason).await));
+        }
+        ret
+    }
+
+    /// Kick every regular client idle longer than `threshold` from server, except
+    /// our own connection. Return ids of clients actually kicked.
+    #[allow(dead_code)]
+    pub async fn kick_idle_clients(
+        &mut self,
+        threshold: Duration,
+        reason: &str,
+    ) -> QueryResult<Vec<i64>> {
+        let me = self.who_am_i().await?;
+        let idle_clients = self
+            .query_clients_with_times()
+            .await?
+            .into_iter()
+            .filter(|client| {
+                client.client_id() != me.client_id()
+                    && client.client_kind() == ClientType::Regular
+                    && client.client_idle_time() > threshold
+            })
+            .map(|client| client.client_id())
+            .collect::<Vec<_>>();
+
+        let mut kicked = Vec::new();
+        for (client_id, ret) in self.kick_clients(&idle_clients, Some(reason)).await {
+            match ret {
+                Ok(_) => kicked.push(client_id),
+                Err(e) => warn!("Kick idle client {} failed: {}", client_id, e),
+            }
+        }
+        Ok(kicked)
+    }
+
+    /// Set description of our current session via `clientedit`, visible at once but
+    /// not persisted, next connection starts with the database record again
+    /// (see `update_client_description`).
+    #[allow(dead_code)]
+    pub async fn update_self_description(&mut self, text: &str) -> QueryResult<()> {
+        let me = self.who_am_i().await?;
+        self.basic_operation(&format!(
+            "clientedit clid={} client_description={}",
+            me.client_id(),
+            Self::escape(text)
+        ))
+        .await
+    }
+
+    async fn file_init(&mut self, command: &str, arguments: String) -> QueryResult<FileTransfer> {
+        let id = self.next_transfer_id;
+        self.next_transfer_id += 1;
+        let transfer = self
+            .query_operation_non_error::<FileTransfer>(&format!(
+                "{} clientftfid={} {}",
+                command, id, arguments
+            ))
+            .await?
+            .remove(0);
+        if transfer.status() != 0 {
+            return Err(QueryStatus::new(transfer.status(), transfer.message()).into());
+        }
+        Ok(transfer)
+    }
+
+    /// Start uploading `size` bytes to `name` (absolute path like `/icon_123`) in
+    /// channel `cid`, use cid 0 for server icons and avatars.
+    #[allow(dead_code)]
+    pub async fn file_init_upload(
+        &mut self,
+        cid: i64,
+        password: Option<&str>,
+        name: &str,
+        size: u64,
+        overwrite: bool,
+    ) -> QueryResult<FileTransfer> {
+        self.file_init(
+            "ftinitupload",
+            format!(
+                "name={} cid={} cpw={} size={} overwrite={} resume=0",
+                Self::escape(name),
+                cid,
+                Self::escape(password.unwrap_or_default()),
+                size,
+                overwrite as u8
+            ),
+        )
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn file_init_download(
+        &mut self,
+        cid: i64,
+        password: Option<&str>,
+        name: &str,
+    ) -> QueryResult<FileTransfer> {
+        self.file_init(
+            "ftinitdownload",
+            format!(
+                "name={} cid={} cpw={} seekpos=0",
+                Self::escape(name),
+                cid,
+                Self::escape(password.unwrap_or_default())
+            ),
+        )
+        .await
+    }
+
+    // File transfer uses its own short-lived connection. Over client query the query
+    // host is the local client, so the server tells where to connect.
+    async fn file_upload(&self, transfer: &FileTransfer, data: &[u8]) -> QueryResult<()> {
+        let host = transfer.host().unwrap_or(&self.server);
+        let address = Self::socket_address(host, transfer.port());
+        let mut stream = TcpStream::connect(&address).await?;
+        let offset = (transfer.seek_position() as usize).min(data.len());
+        stream.write_all(transfer.key().as_bytes()).await?;
+        stream.write_all(&data[offset..]).await?;
+        stream.shutdown().await.ok();
+        Ok(())
+    }
+
+    /// Upload `png` as icon (id is its CRC-32) and use it for channel `cid`
+    #[allow(dead_code)]
+    pub async fn set_channel_icon(&mut self, cid: i64, png: &[u8]) -> QueryResult<u32> {
+        let icon_id = Self::crc32(png);
+        let transfer = self
+            .file_init_upload(
+                0,
+                None,
+                &format!("/icon_{}", icon_id),
+                png.len() as u64,
+                true,
+            )
+            .await?;
+        self.file_upload(&transfer, png).await?;
+        self.channel_edit(cid, &[("channel_icon_id", &icon_id.to_string())])
+            .await?;
+        Ok(icon_id)
+    }
+
+    /// Send private text message to client `clid`
+    #[allow(dead_code)]
+    pub async fn send_private_message(&mut self, clid: i64, message: &str) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "sendtextmessage targetmode=1 target={} msg={}",
+            clid,
+            Self::escape(message)
+        ))
+        .await
+    }
+
+    /// Send private messages one by one with `pace` in between. A message rejected
+    /// by flood protection (524) is retried after a growing delay, result of each
+    /// message is at the same index.
+    #[allow(dead_code)]
+    pub async fn send_text_messages_paced(
+        &mut self,
+        messages: &[(i64, String)],
+        pace: Duration,
+    ) -> Vec<QueryResult<()>> {
+        let policy = RetryPolicy::new(MAX_FLOOD_RETRIES + 1, pace.max(Duration::from_secs(1)))
+            .retry_if(QueryError::is_flooding);
+        let mut ret = Vec::new();
+        for (index, (clid, message)) in messages.iter().enumerate() {
+            if index > 0 {
+                tokio::time::sleep(pace).await;
+            }
+            let clid = *clid;
+            let result = retry(&policy, self, |conn| {
+                let message = message.clone();
+                Box::pin(async move { conn.send_private_message(clid, &message).await })
+            })
+            .await;
+            ret.push(result);
+        }
+        ret
+    }
+
+    /// Text messages of a channel only arrive while subscribed to it
+    #[allow(dead_code)]
+    pub async fn channel_subscribe(&mut self, cids: &[i64]) -> QueryResult<()> {
+        if cids.is_empty() {
+            return Err(QueryError::invalid_argument("cids should not be empty"));
+        }
+        self.basic_operation(&format!(
+            "channelsubscribe {}",
+            Self::format_list("cid", cids)
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn channel_unsubscribe_all(&mut self) -> QueryResult<()> {
+        self.basic_operation("channelunsubscribeall").await
+    }
+
+    /// Update properties of the current connection via `clientupdate`, e.g.
+    /// `client_nickname`, `client_away`, `client_away_message`, `client_input_muted`.
+    #[allow(dead_code)]
+    pub async fn client_update(&mut self, properties: &[(&str, &str)]) -> QueryResult<()> {
+        if properties.is_empty() {
+            return Err(QueryError::invalid_argument(
+                "properties should not be empty",
+            ));
+        }
+        self.basic_operation(&format!(
+            "clientupdate {}",
+            Self::format_properties(properties)
+        ))
+        .await
+    }
+
+    /// Rename the current connection via `clientupdate`, the stored database record
+    /// (which `clientdbedit` writes) is not touched.
+    #[allow(dead_code)]
+    pub async fn set_nickname(&mut self, nickname: &str) -> QueryResult<()> {
+        if nickname.is_empty() {
+            return Err(QueryError::invalid_argument("nickname should not be empty"));
+        }
+        self.client_update(&[("client_nickname", nickname)]).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn set_away(&mut self, message: Option<&str>) -> QueryResult<()> {
+        match message {
+            Some(message) if !message.is_empty() => {
+                self.client_update(&[("client_away", "1"), ("client_away_message", message)])
+                    .await
+            }
+            _ => self.client_update(&[("client_away", "1")]).await,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn clear_away(&mut self) -> QueryResult<()> {
+        self.client_update(&[("client_away", "0")]).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_list(&mut self) -> QueryResult<Vec<VirtualServer>> {
+        self.query_operation_non_error("serverlist").await
+    }
+
+    /// Voice addresses of the instance, only available on server query. Bindings are
+    /// shared by all virtual servers, so the port (e.g. from `server_list`) has to be given.
+    /// Wildcard addresses like `0.0.0.0` are returned as is.
+    #[allow(dead_code)]
+    pub async fn server_bindings(&mut self, port: u16) -> QueryResult<Vec<SocketAddr>> {
+        self.require_interface(Interface::Server, "bindinglist")?;
+        Ok(self
+            .query_operation_non_error::<Binding>("bindinglist subsystem=voice")
+            .await?
+            .into_iter()
+            .map(|binding| SocketAddr::new(binding.ip(), port))
+            .collect())
+    }
+
+    // Map voice port to sid for `use_server`
+    #[allow(dead_code)]
+    pub async fn server_id_get_by_port(&mut self, port: u16) -> QueryResult<i64> {
+        self.require_interface(Interface::Server, "serveridgetbyport")?;
+        self.query_operation_non_error::<ServerId>(&format!(
+            "serveridgetbyport virtualserver_port={}",
+            port
+        ))
+        .await
+        .map(|mut v| v.remove(0).server_id())
+    }
+
+    #[allow(dead_code)]
+    pub async fn use_server(&mut self, sid: i64) -> QueryResult<()> {
+        self.require_interface(Interface::Server, "use")?;
+        self.basic_operation(&format!("use sid={}", sid)).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn use_server_port(&mut self, port: u16) -> QueryResult<()> {
+        self.require_interface(Interface::Server, "use")?;
+        self.basic_operation(&format!("use port={}", port)).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn global_message(&mut self, msg: &str) -> QueryResult<()> {
+        if msg.is_empty() {
+            return Err(QueryError::invalid_argument("message should not be empty"));
+        }
+        self.basic_operation(&format!("gm msg={}", Self::escape(msg)))
+            .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_start(&mut self, sid: i64) -> QueryResult<()> {
+        self.basic_operation(&format!("serverstart sid={}", sid))
+            .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn server_stop(&mut self, sid: i64, reason: Option<&str>) -> QueryResult<()> {
+        match reason {
+            Some(reason) => {
+                self.basic_operation(&format!(
+                    "serverstop sid={} reasonmsg={}",
+                    sid,
+                    Self::escape(reason)
+                ))
+                .await
+            }
+            None => {
+                self.basic_operation(&format!("serverstop sid={}", sid))
+                    .await
+            }
+        }
+    }
+
+    /// Instance wide settings, e.g. `serverinstance_serverquery_flood_commands`
+    #[allow(dead_code)]
+    pub async fn instance_edit(&mut self, properties: &[(&str, &str)]) -> QueryResult<()> {
+        self.require_interface(Interface::Server, "instanceedit")?;
+        if properties.is_empty() {
+            return Err(QueryError::invalid_argument(
+                "properties should not be empty",
+            ));
+        }
+        self.basic_operation(&format!(
+            "instanceedit {}",
+            Self::format_properties(properties)
+        ))
+        .await
+    }
+
+    /// Shut down the whole server process with all virtual servers, `confirm` must be
+    /// true so a stray call can not take the instance down.
+    #[allow(dead_code)]
+    pub async fn server_process_stop(&mut self, confirm: bool) -> QueryResult<()> {
+        self.require_interface(Interface::Server, "serverprocessstop")?;
+        if !confirm {
+            return Err(QueryError::invalid_argument(
+                "serverprocessstop stops the server process, pass confirm = true",
+            ));
+        }
+        self.basic_operation("serverprocessstop").await
+    }
+
+    #[allow(dead_code)]
+    pub async fn log_view(&mut self, lines: u32, reverse: bool) -> QueryResult<Vec<LogEntry>> {
+        let payload = format!("logview lines={} reverse={}", lines, reverse as u8);
+        let ret = self
+            .write_and_read(&payload)
+            .await
+            .and_then(Self::decode_status)
+            .and_then(|content| {
+                content
+                    .lines()
+                    .find(|line| !line.starts_with("error "))
+                    .map(LogEntry::from_reply)
+                    .unwrap_or_else(|| Ok(vec![]))
+            });
+        self.record(ret)
+    }
+
+    #[allow(dead_code)]
+    pub async fn log_add(&mut self, level: LogLevel, message: &str) -> QueryResult<()> {
+        if message.is_empty() {
+            return Err(QueryError::invalid_argument("message should not be empty"));
+        }
+        self.basic_operation(&format!(
+            "logadd loglevel={} logmsg={}",
+            level.value(),
+            Self::escape(message)
+        ))
+        .await
+    }
+
+    // Snapshot blob is returned verbatim, it is already escaped by server and comes
+    // as a single line, which is what `server_snapshot_deploy` accepts
+    #[allow(dead_code)]
+    pub async fn server_snapshot_create(&mut self) -> QueryResult<String> {
+        let ret = self
+            .write_and_read("serversnapshotcreate")
+            .await
+            .and_then(Self::decode_status);
+        let content = self.record(ret)?;
+        let mut lines = content.lines().filter(|line| !line.starts_with("error "));
+        match (lines.next(), lines.next()) {
+            (Some(line), None) => Ok(line.to_string()),
+            _ => Err(QueryError::parse_error(
+                "Snapshot should be a single line".to_string(),
+            )),
+        }
+    }
+
+    // Line break inside would end the command there, rest would be sent as commands
+    #[allow(dead_code)]
+    pub async fn server_snapshot_deploy(&mut self, data: &str) -> QueryResult<()> {
+        let data = data.trim_end();
+        if data.is_empty() {
+            return Err(QueryError::invalid_argument("snapshot should not be empty"));
+        }
+        if data.contains(['\n', '\r']) {
+            return Err(QueryError::invalid_argument(
+                "snapshot should not contain line breaks",
+            ));
+        }
+        self.basic_operation(&format!("serversnapshotdeploy {}", data))
+            .await
+    }
+
+    /// Common keys: `virtualserver_name`, `virtualserver_maxclients`,
+    /// `virtualserver_password`, `virtualserver_welcomemessage`.
+    #[allow(dead_code)]
+    pub async fn server_edit(&mut self, properties: &[(&str, &str)]) -> QueryResult<()> {
+        if properties.is_empty() {
+            return Err(QueryError::invalid_argument(
+                "properties should not be empty",
+            ));
+        }
+        self.basic_operation(&format!(
+            "serveredit {}",
+            Self::format_properties(properties)
+        ))
+        .await
+    }
+
+    // Empty password removes it
+    #[allow(dead_code)]
+    pub async fn set_server_password(&mut self, password: &str) -> QueryResult<()> {
+        self.server_edit(&[("virtualserver_password", password)])
+            .await
+    }
+
+    /// Password to join for `duration` without knowing the server password, into channel
+    /// `target_cid` or default channel if 0
+    #[allow(dead_code)]
+    pub async fn temp_password_add(
+        &mut self,
+        pw: &str,
+        desc: &str,
+        duration: Duration,
+        target_cid: i64,
+    ) -> QueryResult<()> {
+        self.require_interface(Interface::Server, "servertemppasswordadd")?;
+        self.basic_operation(&format!(
+            "servertemppasswordadd pw={} desc={} duration={} tcid={} tcpw=",
+            Self::escape(pw),
+            Self::escape(desc),
+            duration.as_secs(),
+            target_cid
+        ))
+        .await
+    }
+
+    #[allow(dead_code)]
+    pub async fn temp_password_list(&mut self) -> QueryResult<Vec<TempPassword>> {
+        self.require_interface(Interface::Server, "servertemppasswordlist")?;
+        match self
+            .query_operation_non_error("servertemppasswordlist")
+            .await
+        {
+            Err(e) if e.code() == 1281 => Ok(vec![]),
+            ret => ret,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn temp_password_del(&mut self, pw: &str) -> QueryResult<()> {
+        self.require_interface(Interface::Server, "servertemppassworddel")?;
+        self.basic_operation(&format!("servertemppassworddel pw={}", Self::escape(pw)))
+            .await
+    }
+
+    /// Write all commands before reading any reply, each result is the decoded
+    /// reply of the command at same index.
+    #[allow(dead_code)]
+    pub async fn pipeline(&mut self, payloads: &[&str]) -> Vec<QueryResult<String>> {
+        let mut receivers = Vec::new();
+        for payload in payloads {
+            receivers.push(self.submit(payload).await);
+        }
+        let mut ret = Vec::new();
+        for receiver in receivers {
+            let reply = match receiver {
+                Ok(receiver) => Self::wait_reply(receiver, self.reply_timeout)
+                    .await
+                    .and_then(Self::decode_status),
+                Err(e) => Err(e),
+            };
+            ret.push(self.record(reply));
+        }
+        ret
+    }
+
+    #[allow(dead_code)]
+    pub async fn api_key_list(&mut self) -> QueryResult<Vec<ApiKey>> {
+        self.query_operation_non_error("apikeylist").await
+    }
+
+    /// `lifetime` is rounded up to whole days, `None` keeps the client default (14 days).
+    #[allow(dead_code)]
+    pub async fn api_key_add(
+        &mut self,
+        scope: ApiKeyScope,
+        lifetime: Option<Duration>,
+    ) -> QueryResult<ApiKey> {
+        let payload = match lifetime {
+            Some(lifetime) => format!(
+                "apikeyadd scope={} lifetime={}",
+                scope.as_str(),
+                lifetime.as_secs().div_ceil(86400)
+            ),
+            None => format!("apikeyadd scope={}", scope.as_str()),
+        };
+        self.query_operation_non_error(&payload)
+            .await
+            .map(|mut v| v.remove(0))
+    }
+
+    /// Scope a key needs to send `command`: read-only commands need `read`, api key
+    /// management needs `manage`, everything else `write`.
+    pub fn required_scope(command: &str) -> ApiKeyScope {
+        let command = command.split_whitespace().next().unwrap_or_default();
+        if command.starts_with("apikey") {
+            ApiKeyScope::Manage
+        } else if READ_SCOPE_COMMANDS.contains(&command) {
+            ApiKeyScope::Read
+        } else {
+            ApiKeyScope::Write
+        }
+    }
+
+    /// `apikeylist` does not tell which key this connection authenticated with,
+    /// so the widest scope among keys of our own database id is taken. Listing keys
+    /// needs manage scope itself, if that is denied our key is read or write, which
+    /// can not be told apart: `None` is returned for commands which need write.
+    #[allow(dead_code)]
+    pub async fn has_scope_for(&mut self, command: &str) -> QueryResult<Option<bool>> {
+        let required = Self::required_scope(command);
+        let keys = match self.api_key_list().await {
+            Ok(keys) => keys,
+            Err(e) if e.is_permission_denied() => {
+                return Ok(match required {
+                    ApiKeyScope::Read => Some(true),
+                    ApiKeyScope::Write => None,
+                    ApiKeyScope::Manage => Some(false),
+                });
+            }
+            Err(e) => return Err(e),
+        };
+        let database_id = self.query_database_id().await?;
+        Ok(Some(
+            keys.iter()
+                .filter(|key| key.client_database_id() == database_id)
+                .any(|key| key.scope().allows(required)),
+        ))
+    }
+
+    #[allow(dead_code)]
+    pub async fn api_key_del(&mut self, id: i64) -> QueryResult<()> {
+        self.basic_operation(&format!("apikeydel id={}", id)).await
+    }
+
+    pub async fn query_database_id(&mut self) -> QueryResult<i64> {
+        let my = self.who_am_i().await?;
+        let find = |clients: Vec<Client>| {
+            clients
+                .iter()
+                .rev()
+                .find(|client| client.client_id() == my.client_id())
+                .map(Client::client_database_id)
+                .filter(|database_id| *database_id != 0)
+        };
+        let clients = self.query_clients().await?;
+        if let Some(database_id) = find(clients) {
+            return Ok(database_id);
+        }
+        // Right after connect whoami may already know us while clientlist does not yet
+        debug!("Client {} not in client list, retry", my.client_id());
+        tokio::time::sleep(DATABASE_ID_RETRY_DELAY).await;
+        let clients = self.refresh_clients().await?;
+        find(clients).ok_or_else(QueryError::database_id_error)
+    }
+
+    /// Write the stored description of database record `cldbid` via `clientdbedit`,
+    /// it persists across connections and shows up once the client is seen again.
+    pub async fn update_client_description(&mut self, edit_var: ClientEdit) -> QueryResult<()> {
+        self.basic_operation(&format!(
+            "clientdbedit cldbid={} client_description={}",
+            edit_var.client_database_id(),
+            Self::escape(edit_var.description())
+        ))
+        .await
+    }
+
+    // Escaped value can not contain anything which ends a parameter or command
+    fn validate_escaped(raw: &str) -> QueryResult<()> {
+        if raw.contains(|c: char| c.is_whitespace() || c == '|') {
+            return Err(QueryError::invalid_argument(
+                "escaped value should not contain whitespace or '|'",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Same as `update_client_description`, but `raw` is sent as is. Caller must
+    /// escape it already (`\s` for space, `\p` for `|`, `\n` for newline...),
+    /// only unescaped separators are rejected, a wrong escape sequence is not detected
+    /// and ends up in the stored description verbatim.
+    #[allow(dead_code)]
+    pub async fn update_client_description_raw(
+        &mut self,
+        cldbid: i64,
+        raw: &str,
+    ) -> QueryResult<()> {
+        Self::validate_escaped(raw)?;
+        self.basic_operation(&format!(
+            "clientdbedit cldbid={} client_description={}",
+            cldbid, raw
+        ))
+        .await
+    }
+
+    pub async fn query_client_description(
         &mut self,
         client_id: i64,
     ) -> QueryResult<ClientVariable> {
-        self.query_operation(&format!(
-            "clientvariable clid={} client_description\n\r",
-            client_id
+        self.client_variables(client_id, &["client_description"])
+            .await?
+            .remove("client_description")
+            .map(|description| ClientVariable::new(client_id, description))
+            .ok_or_else(|| QueryError::variable_not_found(client_id))
+    }
+
+    // Descriptions of many clients in one round trip
+    #[allow(dead_code)]
+    pub async fn client_descriptions(
+        &mut self,
+        client_ids: &[i64],
+    ) -> QueryResult<Vec<ClientVariable>> {
+        if client_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        self.query_operation_non_error(&format!(
+            "clientvariable {} client_description",
+            Self::format_list("clid", client_ids)
         ))
         .await
-        .map(|x| {
-            x.map(|mut o| o.remove(0))
-                .ok_or_else(|| QueryError::query_error("str"))
-        })?
+    }
+
+    pub async fn client_variables(
+        &mut self,
+        client_id: i64,
+        names: &[&str],
+    ) -> QueryResult<HashMap<String, String>> {
+        let mut variables: HashMap<String, String> = self
+            .query_operation(&format!(
+                "clientvariable clid={} {}",
+                client_id,
+                names.join(" ")
+            ))
+            .await?
+            .map(|mut o| o.remove(0))
+            .ok_or_else(|| QueryError::variable_not_found(client_id))?;
+        variables.retain(|key, _| names.contains(&key.as_str()));
+        Ok(variables)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::datastructures::{ApiKeyScope, Client, GroupType, Notification, QueryError, WhoAmI};
+    use crate::socketlib::{ConnState, Interface, SocketConn};
+    use crate::testutil::{self, mock_server, Step};
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_decode_fixtures() {
+        let me = SocketConn::decode_status_with_result::<WhoAmI>(testutil::WHOAMI.to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(me.len(), 1);
+        assert_eq!(me[0].client_id(), 2);
+        let clients =
+            SocketConn::decode_status_with_result::<Client>(testutil::CLIENTLIST.to_string())
+                .unwrap()
+                .unwrap();
+        assert_eq!(clients[1].client_nickname(), "Some One");
+        let info = SocketConn::decode_status_with_result::<HashMap<String, String>>(
+            testutil::SERVERINFO.to_string(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(info[0]["virtualserver_name"], "TeamSpeak ]I[ Server");
+        assert!(
+            SocketConn::decode_status_with_result::<WhoAmI>(testutil::OK.to_string())
+                .unwrap()
+                .is_none()
+        );
+        let err = SocketConn::decode_status(testutil::ERROR_PERMISSION.to_string()).unwrap_err();
+        assert!(err.is_permission_denied());
+        let err = SocketConn::decode_status(testutil::ERROR_NOT_LOGGED_IN.to_string()).unwrap_err();
+        assert!(err.is_not_logged_in());
+        let err = SocketConn::decode_status(testutil::MALFORMED_STATUS.to_string()).unwrap_err();
+        assert!(err.is_parse_error());
+        let err = SocketConn::decode_status(testutil::MISSING_STATUS.to_string()).unwrap_err();
+        assert_eq!(err.code(), QueryError::STATUS_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_read_loop() {
+        let port = testutil::scripted_server(
+            "",
+            vec![
+                ("whoami", testutil::WHOAMI),
+                ("clientlist", testutil::ERROR_PERMISSION),
+            ],
+        )
+        .await;
+        let (reader, mut writer) = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap()
+            .into_split();
+        let (sender, _events) = tokio::sync::mpsc::channel(1);
+        let pending = crate::socketlib::PendingReplies::default();
+        tokio::spawn(SocketConn::read_loop(
+            reader,
+            pending.clone(),
+            sender,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Vec::new(),
+        ));
+        let mut results = Vec::new();
+        for command in ["whoami\n\r", "clientlist\n\r"] {
+            let (reply, receiver) = tokio::sync::oneshot::channel();
+            pending
+                .lock()
+                .unwrap()
+                .push_back(crate::socketlib::Pending::Reply(reply, None));
+            writer.write_all(command.as_bytes()).await.unwrap();
+            results.push(SocketConn::decode_status(receiver.await.unwrap().unwrap()));
+        }
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            "clid=2 cid=1\nerror id=0 msg=ok"
+        );
+        assert!(results[1].as_ref().unwrap_err().is_permission_denied());
+    }
+
+    #[tokio::test]
+    async fn test_route_notify() {
+        let port = mock_server(vec![
+            "notifycliententerview schandlerid=1 ctid=1 reasonid=0 clid=5\n\rclid=2 cid=1\n\rerror id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let mut events = conn.take_events().unwrap();
+        let me = conn.who_am_i().await.unwrap();
+        assert_eq!(me.client_id(), 2);
+        assert_eq!(me.channel_id(), 1);
+        assert!(matches!(
+            events.recv().await.unwrap().unwrap(),
+            Notification::ClientEnterView(_)
+        ));
+    }
+
+    #[test]
+    fn test_interface_from_banner() {
+        assert_eq!(
+            Interface::from_banner(
+                "TS3 Client\n\rWelcome to the TeamSpeak 3 ClientQuery interface"
+            ),
+            Interface::Client
+        );
+        assert_eq!(
+            Interface::from_banner("TS3\n\rWelcome to the TeamSpeak 3 ServerQuery interface"),
+            Interface::Server
+        );
+        assert_eq!(Interface::from_banner(""), Interface::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_require_interface() {
+        let port = mock_server(Vec::<String>::new()).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.interface(), Interface::Client);
+        assert_eq!(
+            conn.use_server(1).await.unwrap_err().code(),
+            QueryError::UNSUPPORTED_INTERFACE
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let context = [("nickname", "Some One"), ("reason", "spam")];
+        assert_eq!(
+            SocketConn::render_template("{nickname} kicked: {reason}", &context),
+            "Some One kicked: spam"
+        );
+        assert_eq!(
+            SocketConn::render_template("{unknown} {reason} {", &context),
+            "{unknown} spam {"
+        );
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            SocketConn::redact("auth apikey=SECRET"),
+            "auth apikey=<redacted>"
+        );
+        assert_eq!(
+            SocketConn::redact("login client_login_name=serveradmin client_login_password=SECRET"),
+            "login <redacted>"
+        );
+        assert_eq!(SocketConn::redact("whoami"), "whoami");
+    }
+
+    #[test]
+    fn test_split_banner() {
+        let (banner, rest) = SocketConn::split_banner(
+            b"TS3 Client\n\rWelcome to the TeamSpeak 3 ClientQuery interface\n\rnotifyclientleftview clid=7\n\rnotify"
+                .to_vec(),
+        );
+        assert!(banner.starts_with("TS3 Client"));
+        assert_eq!(rest, b"notifyclientleftview clid=7\n\rnotify");
+        let (_, rest) = SocketConn::split_banner(b"TS3\n\r".to_vec());
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drain_notify_before_command() {
+        let port = testutil::scripted_server(
+            // Notify is already buffered before any command is issued
+            "TS3 Client\n\rWelcome to the TeamSpeak 3 ClientQuery interface\n\r\
+            notifyclientleftview schandlerid=1 cfid=1 ctid=0 reasonid=8 clid=7\n\r",
+            vec![(
+                "clientlist",
+                "clid=1 cid=1|clid=2 cid=3\n\r\
+                notifycliententerview schandlerid=1 ctid=1 reasonid=0 clid=5\n\r\
+                error id=0 msg=ok\n\r",
+            )],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let mut events = conn.take_events().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let clients = conn.query_clients().await.unwrap();
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[1].channel_id(), 3);
+        assert!(matches!(
+            events.recv().await.unwrap().unwrap(),
+            Notification::ClientLeftView(_)
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap().unwrap(),
+            Notification::ClientEnterView(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_is_connected() {
+        let port = mock_server(vec!["clid=2 cid=1\n\rerror id=0 msg=ok\n\r"]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert!(conn.is_connected());
+        conn.who_am_i().await.unwrap();
+        assert!(conn.who_am_i().await.unwrap_err().is_connection_closed());
+        assert!(!conn.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_read_loop_eof() {
+        let (client, server) = tokio::io::duplex(64);
+        let (reader, _writer) = tokio::io::split(client);
+        let (sender, _events) = tokio::sync::mpsc::channel(1);
+        let pending = crate::socketlib::PendingReplies::default();
+        let (reply, receiver) = tokio::sync::oneshot::channel();
+        pending
+            .lock()
+            .unwrap()
+            .push_back(crate::socketlib::Pending::Reply(reply, None));
+        let reader = tokio::spawn(SocketConn::read_loop(
+            reader,
+            pending.clone(),
+            sender,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Vec::new(),
+        ));
+        // Half of a reply arrives, then the peer goes away
+        let (_, mut server_writer) = tokio::io::split(server);
+        server_writer.write_all(b"clid=2 ").await.unwrap();
+        drop(server_writer);
+        tokio::time::timeout(Duration::from_secs(1), reader)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(receiver.await.is_err());
+        assert!(pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_undrained_records() {
+        let (client, server) = tokio::io::duplex(256);
+        let (reader, _writer) = tokio::io::split(client);
+        let (sender, _events) = tokio::sync::mpsc::channel(1);
+        let pending = crate::socketlib::PendingReplies::default();
+        let (records, mut stream) = tokio::sync::mpsc::channel(1);
+        let (reply, receiver) = tokio::sync::oneshot::channel();
+        pending.lock().unwrap().extend([
+            crate::socketlib::Pending::Records(records),
+            crate::socketlib::Pending::Reply(reply, None),
+        ]);
+        tokio::spawn(SocketConn::read_loop(
+            reader,
+            pending.clone(),
+            sender,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Vec::new(),
+        ));
+        let (_, mut server_writer) = tokio::io::split(server);
+        server_writer
+            .write_all(
+                format!(
+                    "clid=1|clid=2|clid=3\n\r{}{}",
+                    testutil::OK,
+                    testutil::WHOAMI
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        // Stream is never read, reply behind it still arrives
+        let reply = tokio::time::timeout(Duration::from_secs(10), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply.unwrap(), "clid=2 cid=1\nerror id=0 msg=ok\n");
+        assert_eq!(stream.recv().await.unwrap(), "clid=1");
+        assert!(stream.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_strict_utf8() {
+        const REPLY: &[u8] = b"client_nickname=\xff\n\rerror id=0 msg=ok\n\r";
+        let port = testutil::stepped_server(
+            "TS3 Client\n\r",
+            vec![
+                Step::expect("whoami"),
+                Step::send(REPLY),
+                Step::expect("whoami"),
+                Step::send(REPLY),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let lossy = conn.pipeline(&["whoami"]).await.remove(0).unwrap();
+        assert!(lossy.contains('\u{fffd}'));
+        conn.set_strict_utf8(true);
+        assert_eq!(
+            conn.pipeline(&["whoami"])
+                .await
+                .remove(0)
+                .unwrap_err()
+                .code(),
+            QueryError::INVALID_UTF8
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reply_timeout() {
+        const RECORD: &[u8] = b"clid=2 cid=1";
+        // Never finish the reply within the timeout, one byte each time
+        let dribble = (0..50).flat_map(|i| {
+            [
+                Step::send(&RECORD[i % RECORD.len()..][..1]),
+                Step::Sleep(Duration::from_millis(20)),
+            ]
+        });
+        let port = testutil::stepped_server(
+            "TS3 Client\n\r",
+            std::iter::once(Step::expect("whoami"))
+                .chain(dribble)
+                .collect(),
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.set_reply_timeout(Duration::from_millis(300));
+        assert!(conn.who_am_i().await.unwrap_err().is_timeout());
+    }
+
+    #[tokio::test]
+    async fn test_auth_state() {
+        let port = testutil::scripted_server(
+            testutil::BANNER_AUTH,
+            vec![
+                ("auth apikey=KEY", testutil::OK),
+                ("whoami", testutil::WHOAMI),
+                ("quit", testutil::OK),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert!(!conn.is_authenticated());
+        // Rejected without round trip, server would fail on the unexpected line
+        assert_eq!(
+            conn.who_am_i().await.unwrap_err().code(),
+            QueryError::NOT_AUTHENTICATED
+        );
+        conn.login("KEY").await.unwrap();
+        assert!(conn.is_authenticated());
+        assert_eq!(conn.who_am_i().await.unwrap().client_id(), 2);
+        conn.logout().await.unwrap();
+        assert!(!conn.is_authenticated());
+        assert!(conn.who_am_i().await.unwrap_err().is_connection_closed());
+    }
+
+    #[tokio::test]
+    async fn test_server_login() {
+        let port = testutil::scripted_server(
+            testutil::SERVER_BANNER,
+            vec![
+                (
+                    "login client_login_name=serveradmin client_login_password=WRONG",
+                    "error id=520 msg=invalid\\sloginname\\sor\\spassword\n\r",
+                ),
+                (
+                    "login client_login_name=serveradmin client_login_password=p\\sw",
+                    testutil::OK,
+                ),
+                ("whoami", testutil::WHOAMI),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.interface(), Interface::Server);
+        assert!(!conn.is_authenticated());
+        assert_eq!(
+            conn.who_am_i().await.unwrap_err().code(),
+            QueryError::NOT_AUTHENTICATED
+        );
+        assert_eq!(
+            conn.login("KEY").await.unwrap_err().code(),
+            QueryError::UNSUPPORTED_INTERFACE
+        );
+        assert_eq!(
+            conn.server_login("serveradmin", "WRONG")
+                .await
+                .unwrap_err()
+                .code(),
+            QueryError::AUTH_FAILED
+        );
+        assert!(!conn.is_authenticated());
+        conn.server_login("serveradmin", "p w").await.unwrap();
+        assert!(conn.is_authenticated());
+        assert_eq!(conn.who_am_i().await.unwrap().client_id(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_state_changes() {
+        let port = mock_server(vec![testutil::OK]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let mut state = conn.subscribe_state();
+        assert_eq!(*state.borrow(), ConnState::Authenticated);
+        conn.login("KEY").await.unwrap();
+        // Mock server closes the socket after its last reply
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while *state.borrow() != ConnState::Closed {
+                state.changed().await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+        assert!(!conn.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_login() {
+        let port = mock_server(vec!["error id=1538 msg=invalid\\sparameter\n\r"]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(
+            conn.login("WRONG").await.unwrap_err().code(),
+            QueryError::AUTH_FAILED
+        );
+
+        let port = testutil::stepped_server(
+            testutil::BANNER,
+            vec![
+                Step::expect("auth apikey=KEY"),
+                Step::Sleep(Duration::from_secs(1)),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.set_reply_timeout(Duration::from_secs(5));
+        conn.set_login_timeout(Duration::from_millis(100));
+        assert!(conn.login("KEY").await.unwrap_err().is_timeout());
+    }
+
+    #[tokio::test]
+    async fn test_extra_message() {
+        let port = mock_server(vec![
+            "error id=2568 msg=insufficient\\sclient\\spermissions failed_permid=4 extra_msg=need\\si_client_kick_from_server_power\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let e = conn.kick_client(5, None).await.unwrap_err();
+        assert!(e.is_permission_denied());
+        assert_eq!(
+            e.extra_message(),
+            Some("need i_client_kick_from_server_power")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_server_password() {
+        const PASSWORD: &str = "p@ss word/|\\";
+        // As written on the wire, independent of our own escape
+        const ESCAPED: &str = "p@ss\\sword\\/\\p\\\\";
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![(
+                format!("serveredit virtualserver_password={}", ESCAPED),
+                testutil::OK,
+            )],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.set_server_password(PASSWORD).await.unwrap();
+        // Server echoes the value escaped the same way
+        let echoed = format!(
+            "virtualserver_password={}\n\rerror id=0 msg=ok\n\r",
+            ESCAPED
+        );
+        let info = SocketConn::decode_status_with_result::<HashMap<String, String>>(echoed)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info[0]["virtualserver_password"], PASSWORD);
+    }
+
+    #[tokio::test]
+    async fn test_late_reply() {
+        let port = testutil::stepped_server(
+            testutil::BANNER,
+            vec![
+                Step::expect("whoami"),
+                Step::Sleep(Duration::from_millis(300)),
+                Step::send(testutil::WHOAMI),
+                Step::expect("clientlist"),
+                Step::send(testutil::CLIENTLIST),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.set_reply_timeout(Duration::from_millis(100));
+        assert!(conn.who_am_i().await.unwrap_err().is_timeout());
+        // Late whoami reply goes to the abandoned slot, not to clientlist
+        conn.set_reply_timeout(Duration::from_secs(2));
+        let clients = conn.query_clients().await.unwrap();
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[1].client_nickname(), "Some One");
+    }
+
+    #[tokio::test]
+    async fn test_stray_reply() {
+        // Reply of a channellist nobody waits for any more
+        let reply = format!(
+            "cid=1 pid=0 channel_name=Lobby\n\r{}{}",
+            testutil::OK,
+            testutil::CLIENTLIST
+        );
+        let port = mock_server(vec![reply]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.query_clients().await.unwrap().len(), 2);
+        assert!(SocketConn::is_plausible(
+            "error id=1281 msg=database\\sempty\\sresult\\sset\n",
+            "clid="
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let blob = format!(
+            "hash=abc {}",
+            "virtualserver_name=Some\\sServer\\p".repeat(100)
+        );
+        let reply = format!("{}\n\rerror id=0 msg=ok\n\r", blob);
+        let port = mock_server(vec![reply]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.server_snapshot_create().await.unwrap(), blob);
+        assert_eq!(
+            conn.server_snapshot_deploy("hash=abc virtualserver_name=a\nchannel_name=b")
+                .await
+                .unwrap_err()
+                .code(),
+            QueryError::INVALID_ARGUMENT
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_client() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                ("clientnotifyregister schandlerid=0 event=any", testutil::OK),
+                (
+                    "clientlist -uid",
+                    "clid=1 cid=1 client_database_id=1 client_nickname=me client_type=1 client_unique_identifier=me=\n\rerror id=0 msg=ok\n\r\
+                    notifycliententerview schandlerid=1 cfid=0 ctid=1 reasonid=0 clid=7 client_unique_identifier=other= client_nickname=Other\n\r\
+                    notifycliententerview schandlerid=1 cfid=0 ctid=1 reasonid=0 clid=5 client_unique_identifier=abc= client_nickname=Some\n\r",
+                ),
+                ("clientnotifyunregister", testutil::OK),
+                ("clientnotifyregister schandlerid=0 event=any", testutil::OK),
+                (
+                    "clientlist -uid",
+                    "clid=1 cid=1 client_database_id=1 client_nickname=me client_type=1 client_unique_identifier=me=\n\rerror id=0 msg=ok\n\r",
+                ),
+                // Sent after the timeout, server stays silent until then
+                ("clientnotifyunregister", testutil::OK),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(
+            conn.wait_for_client("abc=", Duration::from_secs(1))
+                .await
+                .unwrap(),
+            5
+        );
+        // Event stream still has every event, also the one which did not match
+        let mut events = conn.take_events().unwrap();
+        for client_id in [7, 5] {
+            assert!(matches!(
+                events.try_recv(),
+                Ok(Ok(Notification::ClientEnterView(view))) if view.client_id() == client_id
+            ));
+        }
+        let e = conn
+            .wait_for_client("nobody=", Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert!(e.is_timeout());
+    }
+
+    #[tokio::test]
+    async fn test_channel_client_perm_list() {
+        let port = mock_server(vec![
+            "cid=2 cldbid=5 permsid=i_channel_talk_power permvalue=50 permnegated=0 permskip=0|permsid=b_channel_join_permanent permvalue=1 permnegated=0 permskip=1\n\rerror id=0 msg=ok\n\r",
+            "error id=1281 msg=database\\sempty\\sresult\\sset\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let perms = conn.channel_client_perm_list(2, 5).await.unwrap();
+        assert_eq!(perms.len(), 2);
+        assert_eq!(perms[0].permsid(), "i_channel_talk_power");
+        assert_eq!(perms[0].permvalue(), 50);
+        assert!(perms[1].permskip());
+        assert!(conn
+            .channel_client_perm_list(2, 6)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pipeline() {
+        let port = testutil::stepped_server(
+            "TS3 Client\n\r",
+            vec![
+                Step::expect("first"),
+                Step::expect("second"),
+                Step::expect("third"),
+                // Reply only after all commands arrived, so they are really in flight together
+                Step::send(b"echo=first\n\rerror id=0 msg=ok\n\r"),
+                Step::send(b"echo=second\n\rerror id=0 msg=ok\n\r"),
+                Step::send(b"echo=third\n\rerror id=0 msg=ok\n\r"),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let ret = conn
+            .pipeline(&["first", "second", "third"])
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ret,
+            vec![
+                "echo=first\nerror id=0 msg=ok",
+                "echo=second\nerror id=0 msg=ok",
+                "echo=third\nerror id=0 msg=ok"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_switch_to_channel_named() {
+        const FOUND: &str =
+            "cid=1 channel_name=Lobby|cid=2 channel_name=Lobby\\sAFK\n\rerror id=0 msg=ok\n\r";
+        let port = mock_server(vec![
+            FOUND,
+            "clid=3 cid=5\n\rerror id=0 msg=ok\n\r",
+            "error id=0 msg=ok\n\r",
+            FOUND,
+            "error id=768 msg=invalid\\schannelID\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.switch_to_channel_named("Lobby", None).await.unwrap();
+        assert_eq!(
+            conn.switch_to_channel_named("Lob", None)
+                .await
+                .unwrap_err()
+                .code(),
+            QueryError::AMBIGUOUS_CHANNEL
+        );
+        assert_eq!(
+            conn.switch_to_channel_named("Nowhere", None)
+                .await
+                .unwrap_err()
+                .code(),
+            QueryError::CHANNEL_NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_text_messages_paced() {
+        let port = mock_server(vec![
+            "error id=0 msg=ok\n\r",
+            "error id=524 msg=client\\sis\\sflooding\n\r",
+            "error id=0 msg=ok\n\r",
+            "error id=512 msg=invalid\\sclientID\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let ret = conn
+            .send_text_messages_paced(
+                &[
+                    (1, "hello".to_string()),
+                    (2, "hello".to_string()),
+                    (3, "hello".to_string()),
+                ],
+                Duration::from_millis(100),
+            )
+            .await;
+        assert!(ret[0].is_ok());
+        assert!(ret[1].is_ok());
+        assert_eq!(ret[2].as_ref().unwrap_err().code(), 512);
+    }
+
+    #[tokio::test]
+    async fn test_set_channel_icon() {
+        // Different from query host, upload to it would be refused
+        let file_listener = TcpListener::bind("127.0.0.2:0").await.unwrap();
+        let file_port = file_listener.local_addr().unwrap().port();
+        let uploaded = tokio::spawn(async move {
+            let (mut stream, _) = file_listener.accept().await.unwrap();
+            let mut data = vec![];
+            stream.read_to_end(&mut data).await.unwrap();
+            data
+        });
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                (
+                    "ftinitupload clientftfid=1 name=\\/icon_3421780262 cid=0 cpw= size=9 overwrite=1 resume=0",
+                    format!(
+                        "clientftfid=1 serverftfid=3 ftkey=KEY port={} ip=0.0.0.0,127.0.0.2 seekpos=0\n\rerror id=0 msg=ok\n\r",
+                        file_port
+                    ),
+                ),
+                (
+                    "channeledit cid=5 channel_icon_id=3421780262",
+                    testutil::OK.to_string(),
+                ),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(
+            conn.set_channel_icon(5, b"123456789").await.unwrap(),
+            0xCBF43926
+        );
+        assert_eq!(uploaded.await.unwrap(), b"KEY123456789");
+    }
+
+    #[tokio::test]
+    async fn test_client_descriptions() {
+        let port = mock_server(vec![
+            "clid=1 client_description=foo\\sbar|clid=2 client_description\n\rerror id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert!(conn.client_descriptions(&[]).await.unwrap().is_empty());
+        let result = conn.client_descriptions(&[1, 2]).await.unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].client_id(), 1);
+        assert_eq!(result[0].description(), "foo bar");
+        assert_eq!(result[1].description(), "");
+    }
+
+    #[tokio::test]
+    async fn test_get_uid_from_clid() {
+        let port = mock_server(vec![
+            "clid=5 cluid=abc\\/def= nickname=Some\\sOne\n\rerror id=0 msg=ok\n\r",
+            // ClientQuery answers with an event only
+            "notifyclientuidfromclid schandlerid=1 clid=5 cluid=abc\\/def= nickname=Some\\sOne\n\rerror id=0 msg=ok\n\r",
+            "clid=5 client_unique_identifier=abc\\/def=\n\rerror id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.get_uid_from_clid(5).await.unwrap(), "abc/def=");
+        assert_eq!(conn.get_uid_from_clid(5).await.unwrap(), "abc/def=");
+    }
+
+    #[tokio::test]
+    async fn test_list_cache() {
+        let port = mock_server(vec![
+            "clid=1 cid=1\n\rerror id=0 msg=ok\n\r",
+            "clid=1 cid=1|clid=2 cid=1\n\rerror id=0 msg=ok\n\r",
+            "error id=0 msg=ok\n\r",
+            "clid=1 cid=2|clid=2 cid=1\n\rerror id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.set_list_cache_ttl(Some(Duration::from_secs(60)));
+        assert_eq!(conn.query_clients().await.unwrap().len(), 1);
+        // Served from cache, no command sent
+        assert_eq!(conn.query_clients().await.unwrap().len(), 1);
+        assert_eq!(conn.refresh_clients().await.unwrap().len(), 2);
+        conn.move_client(1, 2, None, false).await.unwrap();
+        assert_eq!(conn.query_clients().await.unwrap()[0].channel_id(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_move_client_verify() {
+        let port = mock_server(vec![
+            "error id=0 msg=ok\n\r",
+            "clid=1 cid=2|clid=2 cid=1\n\rerror id=0 msg=ok\n\r",
+            "error id=0 msg=ok\n\r",
+            "clid=1 cid=2|clid=2 cid=1\n\rerror id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.move_client(1, 2, None, true).await.unwrap();
+        assert_eq!(
+            conn.move_client(2, 2, None, true).await.unwrap_err().code(),
+            QueryError::MOVE_NOT_APPLIED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_retry() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // First two connections are closed before banner, third one is served
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                drop(listener.accept().await.unwrap());
+            }
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"TS3 Client\n\r").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        });
+        assert!(
+            SocketConn::connect_with_retry("127.0.0.1", port, 1, Duration::from_millis(10))
+                .await
+                .unwrap_err()
+                .is_io_error()
+        );
+        let conn = SocketConn::connect_with_retry("127.0.0.1", port, 2, Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(conn.interface(), Interface::Client);
+    }
+
+    #[tokio::test]
+    async fn test_channel_perm_list() {
+        let port = mock_server(vec![
+            "cid=3 permsid=i_client_needed_talk_power permvalue=50 permnegated=0 permskip=0|permsid=b_channel_join_permanent permvalue=1 permnegated=0 permskip=0\n\rerror id=0 msg=ok\n\r",
+            testutil::ERROR_EMPTY_RESULT,
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let perms = conn.channel_perm_list(3).await.unwrap();
+        assert_eq!(perms.len(), 2);
+        assert_eq!(perms[0].permsid(), "i_client_needed_talk_power");
+        assert_eq!(perms[0].permvalue(), 50);
+        assert!(conn.channel_perm_list(4).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_token_use() {
+        let port = mock_server(vec![
+            "error id=0 msg=ok\n\r",
+            "error id=2561 msg=invalid\\sprivilege\\skey\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.token_use("abc+def=").await.unwrap();
+        assert_eq!(conn.token_use("used").await.unwrap_err().code(), 2561);
+        assert_eq!(
+            conn.token_use("").await.unwrap_err().code(),
+            QueryError::INVALID_ARGUMENT
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clients_in_channel() {
+        let port = mock_server(vec![testutil::CLIENTLIST]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let clients = conn.clients_in_channel(3).await.unwrap();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client_id(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_query_real_clients() {
+        let port = mock_server(vec![testutil::CLIENTLIST]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let clients = conn.query_real_clients().await.unwrap();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client_id(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_query_database_id_retry() {
+        let port = mock_server(vec![
+            "clid=5 cid=1\n\rerror id=0 msg=ok\n\r",
+            "clid=1 cid=1 client_database_id=1\n\rerror id=0 msg=ok\n\r",
+            testutil::CLIENTLIST,
+            "clid=5 cid=1\n\rerror id=0 msg=ok\n\r",
+            "clid=1 cid=1 client_database_id=1\n\rerror id=0 msg=ok\n\r",
+            "clid=1 cid=1 client_database_id=1\n\rerror id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.query_database_id().await.unwrap(), 7);
+        assert_eq!(
+            conn.query_database_id().await.unwrap_err().code(),
+            QueryError::DATABASE_ID_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_subscription() {
+        // Dropped guards unregister, a kept one does not
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                ("clientnotifyregister schandlerid=0 event=any", testutil::OK),
+                ("clientnotifyunregister", testutil::OK),
+                ("clientnotifyregister schandlerid=0 event=any", testutil::OK),
+                ("clientnotifyunregister", testutil::OK),
+                ("whoami", testutil::WHOAMI),
+                ("clientnotifyregister schandlerid=0 event=any", testutil::OK),
+                ("whoami", testutil::WHOAMI),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.register_events()
+            .await
+            .unwrap()
+            .cancel(&mut conn)
+            .await
+            .unwrap();
+        drop(conn.register_events().await.unwrap());
+        conn.who_am_i().await.unwrap();
+        conn.register_events().await.unwrap().keep();
+        conn.who_am_i().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_perm_reset() {
+        let port = mock_server(vec!["token=abc\\/def\n\rerror id=0 msg=ok\n\r"]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.perm_reset().await.unwrap(), "abc/def");
+    }
+
+    #[tokio::test]
+    async fn test_group_add() {
+        let port = mock_server(vec![
+            "sgid=13\n\rerror id=0 msg=ok\n\r",
+            "cgid=9\n\rerror id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.server_group_add("Moderator", 1).await.unwrap(), 13);
+        assert_eq!(conn.channel_group_add("Guest", 1).await.unwrap(), 9);
+    }
+
+    #[tokio::test]
+    async fn test_update_self_description() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                ("whoami", testutil::WHOAMI),
+                (
+                    "clientedit clid=2 client_description=on\\sduty",
+                    testutil::OK,
+                ),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.interface(), Interface::Client);
+        conn.update_self_description("on duty").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_channel_garbled() {
+        // Command which creates something is not sent again, next command is whoami
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                (
+                    "channelcreate channel_name=Private",
+                    "cid=abc\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("whoami", testutil::WHOAMI),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert!(conn
+            .create_channel("Private", &[])
+            .await
+            .unwrap_err()
+            .is_parse_error());
+        assert_eq!(conn.who_am_i().await.unwrap().client_id(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_create_channel_and_join() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                (
+                    "channelcreate channel_name=Private channel_flag_permanent=0",
+                    "cid=9\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("whoami", testutil::WHOAMI),
+                ("clientmove cid=9 clid=2", testutil::OK),
+                (
+                    "channelcreate channel_name=Other",
+                    "cid=10\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("whoami", testutil::WHOAMI),
+                (
+                    "clientmove cid=10 clid=2",
+                    "error id=770 msg=already\\smember\\sof\\schannel\n\r",
+                ),
+                (
+                    "channelcreate channel_name=Full",
+                    "cid=11\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("whoami", testutil::WHOAMI),
+                ("clientmove cid=11 clid=2", testutil::ERROR_PERMISSION),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let (cid, error) = conn
+            .create_channel_and_join("Private", &[("channel_flag_permanent", "0")])
+            .await
+            .unwrap();
+        assert_eq!(cid, 9);
+        assert!(error.is_none());
+        let (cid, error) = conn.create_channel_and_join("Other", &[]).await.unwrap();
+        assert_eq!(cid, 10);
+        assert!(error.is_none());
+        let (cid, error) = conn.create_channel_and_join("Full", &[]).await.unwrap();
+        assert_eq!(cid, 11);
+        assert!(error.unwrap().is_permission_denied());
+    }
+
+    #[tokio::test]
+    async fn test_temp_password() {
+        let port = testutil::scripted_server(
+            testutil::SERVER_BANNER,
+            vec![
+                (
+                    "login client_login_name=serveradmin client_login_password=PASS",
+                    testutil::OK,
+                ),
+                (
+                    "servertemppasswordadd pw=event\\s2024 desc=Friday\\/night duration=3600 tcid=5 tcpw=",
+                    testutil::OK,
+                ),
+                (
+                    "servertemppasswordlist",
+                    "nickname=serveradmin uid=serveradmin desc=Friday\\/night pw_clear=event\\s2024 start=1700000000 end=1700003600 tcid=5\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("servertemppassworddel pw=event\\s2024", testutil::OK),
+                ("servertemppasswordlist", testutil::ERROR_EMPTY_RESULT),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.server_login("serveradmin", "PASS").await.unwrap();
+        conn.temp_password_add("event 2024", "Friday/night", Duration::from_secs(3600), 5)
+            .await
+            .unwrap();
+        let passwords = conn.temp_password_list().await.unwrap();
+        assert_eq!(passwords.len(), 1);
+        assert_eq!(passwords[0].nickname(), "serveradmin");
+        assert_eq!(passwords[0].desc(), "Friday/night");
+        assert_eq!(passwords[0].end(), 1700003600);
+        assert_eq!(passwords[0].target_channel_id(), 5);
+        conn.temp_password_del("event 2024").await.unwrap();
+        assert!(conn.temp_password_list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_group_list_filter() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                (
+                    "servergrouplist",
+                    "sgid=1 name=Guest\\sQuery type=2|sgid=5 name=Template type=0|sgid=6 name=Admin type=1\n\rerror id=0 msg=ok\n\r",
+                ),
+                (
+                    "channelgrouplist",
+                    "cgid=1 name=Channel\\sAdmin type=0|cgid=5 name=Channel\\sAdmin type=1\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("channelgrouprename cgid=5 name=Room\\sOwner", testutil::OK),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let groups = conn
+            .server_group_list(Some(GroupType::Regular))
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].sgid(), 6);
+        assert_eq!(conn.channel_group_list(None).await.unwrap().len(), 2);
+        conn.channel_group_rename(5, "Room Owner").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_group_perm() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                ("servergrouprename sgid=7 name=Senior\\sModerator", testutil::OK),
+                (
+                    "servergroupaddperm sgid=7 permsid=i_client_talk_power permvalue=75 permskip=1 permnegated=0",
+                    testutil::OK,
+                ),
+                (
+                    "servergroupaddperm sgid=7 permsid=b_client_ignore_bans permvalue=1 permskip=0 permnegated=1",
+                    testutil::ERROR_PERMISSION,
+                ),
+                (
+                    "servergroupdelperm sgid=7 permsid=i_client_talk_power",
+                    testutil::OK,
+                ),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.server_group_rename(7, "Senior Moderator")
+            .await
+            .unwrap();
+        conn.server_group_add_perm(7, "i_client_talk_power", 75, true, false)
+            .await
+            .unwrap();
+        assert!(conn
+            .server_group_add_perm(7, "b_client_ignore_bans", 1, false, true)
+            .await
+            .unwrap_err()
+            .is_permission_denied());
+        conn.server_group_del_perm(7, "i_client_talk_power")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_group_copy() {
+        let port = mock_server(vec![
+            "sgid=12\n\rerror id=0 msg=ok\n\r",
+            "error id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(
+            conn.server_group_copy(6, 0, "Admin copy", 1).await.unwrap(),
+            12
+        );
+        assert_eq!(conn.channel_group_copy(5, 9, "", 1).await.unwrap(), 9);
+    }
+
+    #[tokio::test]
+    async fn test_ban_add() {
+        let port = mock_server(vec![
+            "banid=3\n\rerror id=0 msg=ok\n\r",
+            "banid=4\n\rerror id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(
+            conn.ban_add_ip(
+                "192.0.2.7",
+                Some(Duration::from_secs(3600)),
+                Some("spam bot")
+            )
+            .await
+            .unwrap(),
+            3
+        );
+        assert_eq!(conn.ban_add_uid("abc=", None, None).await.unwrap(), 4);
+        // Refused locally, mock would not answer a third command
+        assert_eq!(
+            conn.ban_del_all(false).await.unwrap_err().code(),
+            QueryError::INVALID_ARGUMENT
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ban_add_template() {
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                (
+                    "banadd ip=192.0.2.7 time=600 banreason=Some\\sOne:\\sflooding\\sfor\\s600\\sseconds",
+                    "banid=5\n\rerror id=0 msg=ok\n\r",
+                ),
+                (
+                    "banadd uid=abc= banreason=Some\\sOne:\\scheating\\s(forever)",
+                    "banid=6\n\rerror id=0 msg=ok\n\r",
+                ),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(
+            conn.ban_add_ip_template(
+                "192.0.2.7",
+                Some(Duration::from_secs(600)),
+                "{nickname}: {reason} for {duration}",
+                &[("nickname", "Some One"), ("reason", "flooding")],
+            )
+            .await
+            .unwrap(),
+            5
+        );
+        assert_eq!(
+            conn.ban_add_uid_template(
+                "abc=",
+                None,
+                "{nickname}: {reason} ({duration})",
+                &[
+                    ("nickname", "Some One"),
+                    ("reason", "cheating"),
+                    ("duration", "forever")
+                ],
+            )
+            .await
+            .unwrap(),
+            6
+        );
+    }
+
+    #[tokio::test]
+    async fn test_instance_management() {
+        let port = testutil::scripted_server(
+            testutil::SERVER_BANNER,
+            vec![
+                (
+                    "login client_login_name=serveradmin client_login_password=PASS",
+                    testutil::OK,
+                ),
+                (
+                    "instanceedit serverinstance_serverquery_flood_commands=50",
+                    testutil::OK,
+                ),
+                ("serverprocessstop", testutil::OK),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.server_login("serveradmin", "PASS").await.unwrap();
+        conn.instance_edit(&[("serverinstance_serverquery_flood_commands", "50")])
+            .await
+            .unwrap();
+        assert_eq!(
+            conn.server_process_stop(false).await.unwrap_err().code(),
+            QueryError::INVALID_ARGUMENT
+        );
+        conn.server_process_stop(true).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ensure_channel() {
+        let port = mock_server(vec![
+            "cid=1 channel_name=Lobby\n\rerror id=0 msg=ok\n\r",
+            "cid=7\n\rerror id=0 msg=ok\n\r",
+            "error id=768 msg=invalid\\schannelID\n\r",
+            "error id=771 msg=channel\\sname\\sis\\salready\\sin\\suse\n\r",
+            "cid=9 channel_name=Music\n\rerror id=0 msg=ok\n\r",
+            "cid=1 channel_name=Lobby\n\rerror id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(
+            conn.ensure_channel("Lob", &[("channel_flag_permanent", "1")])
+                .await
+                .unwrap(),
+            7
+        );
+        assert_eq!(conn.ensure_channel("Music", &[]).await.unwrap(), 9);
+        assert_eq!(conn.ensure_channel("Lobby", &[]).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let port = mock_server(vec![
+            "clid=2 cid=1\n\rerror id=0 msg=ok\n\r",
+            "error id=1794 msg=not\\slogged\\sin\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.who_am_i().await.unwrap();
+        assert_eq!(conn.who_am_i().await.unwrap_err().code(), 1794);
+        let stats = conn.stats();
+        assert_eq!(stats.commands_sent(), 2);
+        assert_eq!(stats.bytes_written(), 2 * "whoami\n\r".len() as u64);
+        assert!(stats.bytes_read() > 0);
+        assert_eq!(stats.errors().get(&1794), Some(&1));
+        assert_eq!(stats.error_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let port = mock_server(vec!["clid=2 cid=1\n\rerror id=0 msg=ok\n\r"]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        conn.set_dry_run(true);
+        conn.set_nickname("nobody").await.unwrap();
+        assert!(conn
+            .create_channel("Some Channel", &[])
+            .await
+            .unwrap_err()
+            .is_dry_run());
+        assert_eq!(conn.who_am_i().await.unwrap().client_id(), 2);
+        assert_eq!(conn.stats().commands_sent(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_clients_stream() {
+        let records = (1..=100)
+            .map(|clid| {
+                format!(
+                    "clid={} cid=1 client_database_id={} client_nickname=user{} client_type=0",
+                    clid, clid, clid
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("|");
+        let reply = format!("{}\n\rerror id=0 msg=ok\n\r", records);
+        let port = mock_server(vec![reply, testutil::WHOAMI.to_string()]).await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let mut stream = conn.query_clients_stream().await.unwrap();
+        let mut count = 0;
+        while let Some(client) = stream.next().await {
+            count += 1;
+            assert_eq!(client.unwrap().client_id(), count);
+        }
+        assert_eq!(count, 100);
+        assert_eq!(conn.who_am_i().await.unwrap().client_id(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_kick_idle_clients() {
+        let port = mock_server(vec![
+            "clid=1 cid=1\n\rerror id=0 msg=ok\n\r",
+            "clid=1 cid=1 client_database_id=1 client_nickname=me client_type=0 client_idle_time=60000|\
+            clid=2 cid=1 client_database_id=2 client_nickname=idle client_type=0 client_idle_time=10000|\
+            clid=3 cid=1 client_database_id=3 client_nickname=active client_type=0 client_idle_time=10|\
+            clid=4 cid=1 client_database_id=4 client_nickname=bot client_type=1 client_idle_time=60000\n\r\
+            error id=0 msg=ok\n\r",
+            "error id=0 msg=ok\n\r",
+        ])
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        let kicked = conn
+            .kick_idle_clients(Duration::from_secs(5), "idle")
+            .await
+            .unwrap();
+        assert_eq!(kicked, vec![2]);
+    }
+
+    #[test]
+    fn test_format_properties() {
+        assert_eq!(
+            SocketConn::format_properties(&[
+                ("client_away", "1"),
+                ("client_away_message", "be right/back")
+            ]),
+            "client_away=1 client_away_message=be\\sright\\/back"
+        );
+    }
+
+    #[test]
+    fn test_validate_channel_name() {
+        assert!(SocketConn::validate_channel_name("Lobby / AFK").is_ok());
+        assert!(SocketConn::validate_channel_name(&"频".repeat(40)).is_ok());
+        for name in ["", "  ", &"a".repeat(41), "line\nbreak"] {
+            assert_eq!(
+                SocketConn::validate_channel_name(name).unwrap_err().code(),
+                QueryError::INVALID_ARGUMENT
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_has_scope_for() {
+        // Key with manage scope lists keys, our database id only has a write key
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                (
+                    "apikeylist",
+                    "id=1 sid=0 cldbid=7 scope=write time_left=unlimited created_at=1600000000|id=2 sid=0 cldbid=3 scope=manage time_left=unlimited created_at=1600000000\n\rerror id=0 msg=ok\n\r",
+                ),
+                ("whoami", testutil::WHOAMI),
+                (
+                    "clientlist",
+                    "clid=2 cid=1 client_database_id=7 client_nickname=me client_type=0\n\rerror id=0 msg=ok\n\r",
+                ),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(
+            conn.has_scope_for("clientdbedit cldbid=7").await.unwrap(),
+            Some(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_has_scope_for_denied() {
+        // Read or write key may not list keys, write commands are undetermined
+        let port = testutil::scripted_server(
+            testutil::BANNER,
+            vec![
+                ("apikeylist", testutil::ERROR_PERMISSION),
+                ("apikeylist", testutil::ERROR_PERMISSION),
+                ("apikeylist", testutil::ERROR_PERMISSION),
+            ],
+        )
+        .await;
+        let mut conn = SocketConn::connect("127.0.0.1", port).await.unwrap();
+        assert_eq!(conn.has_scope_for("clientlist").await.unwrap(), Some(true));
+        assert_eq!(
+            conn.has_scope_for("clientdbedit cldbid=1 client_description=x")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            conn.has_scope_for("apikeyadd scope=read").await.unwrap(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(SocketConn::required_scope("clientlist"), ApiKeyScope::Read);
+        assert_eq!(
+            SocketConn::required_scope("clientdbedit cldbid=1 client_description=x"),
+            ApiKeyScope::Write
+        );
+        assert_eq!(
+            SocketConn::required_scope("apikeylist"),
+            ApiKeyScope::Manage
+        );
+        assert_eq!(
+            SocketConn::required_scope("auth apikey=x"),
+            ApiKeyScope::Read
+        );
+        assert_eq!(
+            SocketConn::required_scope("clientnotifyregister schandlerid=1 event=any"),
+            ApiKeyScope::Read
+        );
+        assert_eq!(
+            SocketConn::required_scope("clientkick clid=5"),
+            ApiKeyScope::Write
+        );
+    }
+
+    #[test]
+    fn test_validate_escaped() {
+        assert!(SocketConn::validate_escaped("[b]bold[/b]\\sand\\pmore").is_ok());
+        for raw in ["two words", "line\n", "a|b"] {
+            assert_eq!(
+                SocketConn::validate_escaped(raw).unwrap_err().code(),
+                QueryError::INVALID_ARGUMENT
+            );
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(SocketConn::crc32(b""), 0);
+        assert_eq!(SocketConn::crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_format_list() {
+        assert_eq!(
+            SocketConn::format_list("cid", &[1, 5, 7]),
+            "cid=1|cid=5|cid=7"
+        );
+        assert_eq!(SocketConn::format_list("cid", &[3]), "cid=3");
+    }
+
+    #[test]
+    fn test_decode_status() {
+        let content = "TS3 Client\n\rWelcome to the TeamSpeak 3 ClientQuery interface\n\r\
+            notifytalkstatuschange schandlerid=1 status=1 isreceivedwhisper=0 clid=2\n\r\
+            clid=2 cid=1\n\rerror id=0 msg=ok\n\r";
+        assert_eq!(
+            SocketConn::decode_status(content.to_string()).unwrap(),
+            "clid=2 cid=1\nerror id=0 msg=ok"
+        );
+        let content =
+            "notifytalkstatuschange schandlerid=1 status=0 isreceivedwhisper=0 clid=2\n\r";
+        assert!(SocketConn::decode_status(content.to_string())
+            .unwrap()
+            .starts_with("notify"));
+        let content = "error id=1794 msg=not\\sconnected\n\r";
+        assert_eq!(
+            SocketConn::decode_status(content.to_string())
+                .unwrap_err()
+                .code(),
+            1794
+        );
+    }
+
+    #[test]
+    fn test_socket_address() {
+        for (host, expect) in [
+            ("::1", "[::1]:25639"),
+            ("[::1]", "[::1]:25639"),
+            ("127.0.0.1", "127.0.0.1:25639"),
+            ("localhost", "localhost:25639"),
+        ] {
+            assert_eq!(SocketConn::socket_address(host, 25639), expect);
+        }
+        assert!(SocketConn::socket_address("::1", 25639)
+            .parse::<SocketAddr>()
+            .is_ok());
     }
 }
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// Shared between connection and its reader task, plain atomics on the hot path,
+// the error map is only locked when a command failed.
+#[derive(Debug, Default)]
+pub struct Counters {
+    commands_sent: AtomicU64,
+    bytes_written: AtomicU64,
+    bytes_read: AtomicU64,
+    errors: Mutex<HashMap<i32, u64>>,
+}
+
+impl Counters {
+    pub fn command_sent(&self, bytes: usize) {
+        self.commands_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn bytes_read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn error(&self, code: i32) {
+        *self.errors.lock().unwrap().entry(code).or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> ConnStats {
+        ConnStats {
+            commands_sent: self.commands_sent.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            errors: self.errors.lock().unwrap().clone(),
+            reconnects: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConnStats {
+    commands_sent: u64,
+    bytes_written: u64,
+    bytes_read: u64,
+    errors: HashMap<i32, u64>,
+    reconnects: u64,
+}
+
+impl ConnStats {
+    pub fn commands_sent(&self) -> u64 {
+        self.commands_sent
+    }
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+    pub fn errors(&self) -> &HashMap<i32, u64> {
+        &self.errors
+    }
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+    // Only tracked by ConnectionManager, a single connection never reconnects itself
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+    pub fn with_reconnects(mut self, reconnects: u64) -> Self {
+        self.reconnects = reconnects;
+        self
+    }
+}
@@ -0,0 +1,124 @@
+// Canned replies and mock peers for tests, recorded from TeamSpeak 3 client/server query
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+pub const BANNER: &str = "TS3 Client\n\rWelcome to the TeamSpeak 3 ClientQuery interface, type \"help\" for a list of commands and \"help <command>\" for information on a specific command.\n\r";
+pub const SERVER_BANNER: &str = "TS3\n\rWelcome to the TeamSpeak 3 ServerQuery interface, type \"help\" for a list of commands and \"help <command>\" for information on a specific command.\n\r";
+// Banner of clients which require an api key
+pub const BANNER_AUTH: &str = "TS3 Client\n\rWelcome to the TeamSpeak 3 ClientQuery interface, type \"help\" for a list of commands and \"help <command>\" for information on a specific command.\n\rUse the \"auth\" command to authenticate yourself. Learn more by typing \"help auth\".\n\r";
+
+pub const OK: &str = "error id=0 msg=ok\n\r";
+pub const WHOAMI: &str = "clid=2 cid=1\n\rerror id=0 msg=ok\n\r";
+pub const CLIENTLIST: &str = "clid=1 cid=1 client_database_id=1 client_nickname=serveradmin client_type=1|clid=5 cid=3 client_database_id=7 client_nickname=Some\\sOne client_type=0\n\rerror id=0 msg=ok\n\r";
+pub const SERVERINFO: &str = "virtualserver_unique_identifier=gAbCde+fGhIjK= virtualserver_name=TeamSpeak\\s]I[\\sServer virtualserver_welcomemessage=Welcome\\sto\\sTeamSpeak virtualserver_platform=Linux virtualserver_version=3.13.7\\s[Build:\\s1655727713] virtualserver_maxclients=32 virtualserver_clientsonline=2 virtualserver_port=9987\n\rerror id=0 msg=ok\n\r";
+
+pub const ERROR_NOT_LOGGED_IN: &str = "error id=1794 msg=not\\slogged\\sin\n\r";
+pub const ERROR_PERMISSION: &str =
+    "error id=2568 msg=insufficient\\sclient\\spermissions failed_permid=4\n\r";
+pub const ERROR_EMPTY_RESULT: &str = "error id=1281 msg=database\\sempty\\sresult\\sset\n\r";
+
+// Status line with non numeric id
+pub const MALFORMED_STATUS: &str = "error id=abc msg=ok\n\r";
+// Records without status line, e.g. reply cut off
+pub const MISSING_STATUS: &str = "clid=2 cid=1\n\r";
+
+// Send banner, then answer every request line with next reply, connection is closed
+// after last one
+pub async fn mock_server(replies: Vec<impl Into<String>>) -> u16 {
+    serve(
+        BANNER,
+        vec![replies
+            .into_iter()
+            .flat_map(|reply| [Step::Skip, Step::Send(reply.into().into_bytes())])
+            .collect()],
+    )
+    .await
+}
+
+// Listen on TCP behind `banner` for `SocketConn::connect`, every request line must equal
+// the scripted command before its reply is written
+pub async fn scripted_server(
+    banner: &'static str,
+    script: Vec<(impl Into<String>, impl Into<String>)>,
+) -> u16 {
+    scripted_sessions(banner, vec![script]).await
+}
+
+// One script per accepted connection, in order, for code which reconnects
+pub async fn scripted_sessions(
+    banner: &'static str,
+    sessions: Vec<Vec<(impl Into<String>, impl Into<String>)>>,
+) -> u16 {
+    serve(
+        banner,
+        sessions
+            .into_iter()
+            .map(|script| {
+                script
+                    .into_iter()
+                    .flat_map(|(request, reply)| {
+                        [Step::expect(request), Step::Send(reply.into().into_bytes())]
+                    })
+                    .collect()
+            })
+            .collect(),
+    )
+    .await
+}
+
+// Step of a `stepped_server` session, for peers which answer late, in pieces or with
+// bytes which are not valid UTF-8
+pub enum Step {
+    // Next request line must equal this command
+    Expect(String),
+    // Next request line, whatever it is
+    Skip,
+    Send(Vec<u8>),
+    Sleep(Duration),
+}
+
+impl Step {
+    pub fn expect(request: impl Into<String>) -> Self {
+        Self::Expect(request.into())
+    }
+    pub fn send(data: impl Into<Vec<u8>>) -> Self {
+        Self::Send(data.into())
+    }
+}
+
+pub async fn stepped_server(banner: &'static str, steps: Vec<Step>) -> u16 {
+    serve(banner, vec![steps]).await
+}
+
+async fn serve(banner: &'static str, sessions: Vec<Vec<Step>>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        for steps in sessions {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            writer.write_all(banner.as_bytes()).await.unwrap();
+            let mut lines = BufReader::new(reader).lines();
+            for step in steps {
+                match step {
+                    Step::Expect(request) => {
+                        let line = lines.next_line().await.unwrap().unwrap_or_default();
+                        assert_eq!(line.trim(), request);
+                    }
+                    Step::Skip => {
+                        assert!(lines.next_line().await.unwrap().is_some());
+                    }
+                    // Peer may be gone already, e.g. after a timeout
+                    Step::Send(data) => {
+                        if writer.write_all(&data).await.is_err() {
+                            break;
+                        }
+                    }
+                    Step::Sleep(duration) => tokio::time::sleep(duration).await,
+                }
+            }
+        }
+    });
+    port
+}